@@ -2,7 +2,7 @@
 
 use frooxengine_rs::*;
 
-#[export_function]
+#[export_function(category = "Math", description = "Squares the sine of a number.")]
 fn sin_square(n: f32) -> f32 {
     let x = n.sin();
     x * x
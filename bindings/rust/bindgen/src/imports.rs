@@ -64,6 +64,21 @@ impl WasmType {
             Self::TemplateFloat => return None,
         })
     }
+
+    /// Field name in the `__import_results` convention of `wasm-weaver`, if the type can be
+    /// stored in memory.
+    pub const fn field(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 | Self::Pointer | Self::PointerMut => "i32",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::V128 => "v128",
+            Self::Externref | Self::TemplateFloat => return None,
+        })
+    }
 }
 
 impl Display for WasmType {
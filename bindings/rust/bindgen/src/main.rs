@@ -2,10 +2,9 @@ use std::{error::Error, io::Write};
 
 use clap::Parser;
 
-use crate::imports::{ImportItem, WasmType};
+use bindgen::imports::{self, ImportItem, WasmType};
 
 mod args;
-mod imports;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = args::BuildArgs::parse();
@@ -32,25 +31,38 @@ fn main() -> Result<(), Box<dyn Error>> {
         )?;
         writeln!(out, r#"    unsafe extern "C" {{"#)?;
 
-        for (name, item) in items {
-            if name.contains("$f") {
-                for instance in [WasmType::F32, WasmType::F64] {
-                    let name = name.replace("$f", instance.typ().unwrap());
-                    write_item(&mut out, &name, &item, Some(&instance))?;
-                }
-                continue;
+        for (name, item) in &items {
+            for (name, instance) in instances(name) {
+                write_item(&mut out, &name, item, instance)?;
             }
-
-            write_item(&mut out, &name, &item, None)?;
         }
 
         writeln!(out, r#"    }}"#)?;
+
+        for (name, item) in &items {
+            for (name, instance) in instances(name) {
+                write_shim(&mut out, &ns, &name, item, instance)?;
+            }
+        }
+
         writeln!(out, r#"}}"#)?;
     }
 
     Ok(())
 }
 
+/// Expands `$f` templates into an `f32` and an `f64` instance.
+fn instances(name: &str) -> Vec<(String, Option<&'static WasmType>)> {
+    static FLOATS: [WasmType; 2] = [WasmType::F32, WasmType::F64];
+    if !name.contains("$f") {
+        return vec![(name.to_string(), None)];
+    }
+    FLOATS
+        .iter()
+        .map(|instance| (name.replace("$f", instance.typ().unwrap()), Some(instance)))
+        .collect()
+}
+
 fn write_item(
     mut out: impl Write,
     name: &str,
@@ -64,7 +76,7 @@ fn write_item(
             results,
         } => {
             if results.len() > 1 {
-                return Ok(());
+                return Ok(()); // see write_shim
             }
             for doc_line in doc {
                 writeln!(out, r#"        #[doc = "{}"]"#, doc_line.escape_default())?;
@@ -88,10 +100,99 @@ fn write_item(
             }
             writeln!(out, ";")?;
         }
-        ImportItem::Global { doc, value_type } => {
+        ImportItem::Global { .. } => {
             return Ok(()); // TODO
         }
     }
 
     Ok(())
 }
+
+/// Writes a function returning a tuple for an import with multiple results. It passes a
+/// pointer to the results, which `wasm-weaver` turns into a multi-value call.
+fn write_shim<'a>(
+    mut out: impl Write,
+    ns: &str,
+    name: &str,
+    item: &'a ImportItem,
+    instance: Option<&'a WasmType>,
+) -> std::io::Result<()> {
+    let ImportItem::Function {
+        doc,
+        parameters,
+        results,
+    } = item
+    else {
+        return Ok(());
+    };
+    if results.len() <= 1 {
+        return Ok(());
+    }
+    let instantiate = |ty: &'a WasmType| match instance {
+        Some(instance) => ty.make_instance(instance),
+        None => ty,
+    };
+    let parameters = parameters.iter().map(instantiate).collect::<Vec<_>>();
+    let results = results.iter().map(instantiate).collect::<Vec<_>>();
+    let Some(fields) = results
+        .iter()
+        .map(|ty| ty.field())
+        .collect::<Option<Vec<_>>>()
+    else {
+        eprintln!("Skipping {ns}.{name}: its results can't be passed through memory");
+        return Ok(());
+    };
+
+    let params = (parameters.iter().enumerate())
+        .map(|(i, param)| format!("arg{i}: {param}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let args = (0..parameters.len())
+        .map(|i| format!("arg{i}, "))
+        .collect::<String>();
+    let types = results
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let bindings = (0..results.len())
+        .map(|i| format!("r{i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    for doc_line in doc {
+        writeln!(out, r#"    #[doc = "{}"]"#, doc_line.escape_default())?;
+    }
+    writeln!(out, "    pub unsafe fn {name}({params}) -> ({types}) {{")?;
+    writeln!(out, "        #[repr(C)]")?;
+    writeln!(out, "        struct Results({types});")?;
+    writeln!(
+        out,
+        r#"        #[link(wasm_import_module = "__import_results:{}")]"#,
+        ns.escape_default()
+    )?;
+    writeln!(out, r#"        unsafe extern "C" {{"#)?;
+    writeln!(
+        out,
+        r#"            #[link_name = "{name}:{}"]"#,
+        fields.join(",")
+    )?;
+    writeln!(
+        out,
+        "            fn {name}({params}{}results: *mut Results);",
+        if params.is_empty() { "" } else { ", " }
+    )?;
+    writeln!(out, "        }}")?;
+    writeln!(
+        out,
+        "        let mut results = core::mem::MaybeUninit::<Results>::uninit();"
+    )?;
+    writeln!(out, "        let Results({bindings}) = unsafe {{")?;
+    writeln!(out, "            {name}({args}results.as_mut_ptr());")?;
+    writeln!(out, "            results.assume_init()")?;
+    writeln!(out, "        }};")?;
+    writeln!(out, "        ({bindings})")?;
+    writeln!(out, "    }}")?;
+
+    Ok(())
+}
@@ -4,7 +4,7 @@ use proc_macro2::{Span, TokenStream};
 use quote::{quote, quote_spanned};
 use unsynn::TokenIter;
 
-use crate::parser::{ExportFunction, ReturnTypes};
+use crate::parser::{ExportAttrs, ExportFunction, ReturnTypes};
 
 extern crate proc_macro;
 
@@ -13,13 +13,21 @@ pub fn export_function(
     attr: proc_macro::TokenStream,
     item: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    if let Some(tok) = attr.into_iter().next() {
-        let span = tok.span().into();
-        return quote_spanned! {span=>
-            compile_error!("unexpected argument");
+    let attr = TokenStream::from(attr);
+    let attrs = match ExportAttrs::parse(&mut TokenIter::new(attr.clone().into_iter())) {
+        Ok(v) => v,
+        Err(error) => {
+            let message = string_literal(error);
+            let span = attr
+                .into_iter()
+                .next()
+                .map_or_else(Span::call_site, |it| it.span());
+            return quote_spanned! {span=>
+                compile_error!(#message);
+            }
+            .into();
         }
-        .into();
-    }
+    };
 
     let mut item_iter = TokenIter::new(TokenStream::from(item).into_iter());
 
@@ -78,6 +86,10 @@ pub fn export_function(
     let args_quote2 = args_quote.clone();
     let arg_names = args.iter().map(|(name, _)| name);
 
+    let node_info = node_info(&name.to_string(), &args, &attrs);
+    let node_info_len = node_info.len();
+    let node_info = proc_macro2::Literal::byte_string(node_info.as_bytes());
+
     let wrap_returns = returns
         .as_ref()
         .map(|r| quote! { -> #r })
@@ -99,6 +111,11 @@ pub fn export_function(
                 __return::#unspan_name(#(#ret_vars2),*)
             }
 
+            /// Read by `wasm-weaver` to describe the node.
+            #[used]
+            #[unsafe(link_section = "frooxengine.node_info")]
+            static NODE_INFO: [u8; #node_info_len] = *#node_info;
+
             mod __return {
                 #[link(wasm_import_module = "__export_returns")]
                 unsafe extern "C" {
@@ -110,6 +127,47 @@ pub fn export_function(
     .into()
 }
 
+/// A JSON object with the names of the export and its parameters and the attribute arguments.
+fn node_info(
+    name: &str,
+    args: &[(proc_macro2::Ident, TokenStream)],
+    attrs: &ExportAttrs,
+) -> String {
+    let params = args
+        .iter()
+        .map(|(name, _)| json_string(name.to_string().trim_start_matches("r#")))
+        .collect::<Vec<_>>();
+    let mut info = format!(
+        r#"{{"export":{},"params":[{}]"#,
+        json_string(name),
+        params.join(",")
+    );
+    if let Some(category) = &attrs.category {
+        info += &format!(r#","category":{}"#, json_string(category));
+    }
+    if let Some(description) = &attrs.description {
+        info += &format!(r#","description":{}"#, json_string(description));
+    }
+    info.push('}');
+    info
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
 fn string_literal(str: impl AsRef<str>) -> proc_macro2::TokenTree {
     proc_macro2::TokenTree::Literal(proc_macro2::Literal::string(str.as_ref()))
 }
@@ -62,6 +62,12 @@ unsynn! {
         _pub: KPub,
         _opt: Option<Skip<ParenthesisGroup>>,
     }
+
+    struct AttrArg {
+        key: Ident,
+        _eq: Skip<Assign>,
+        value: LiteralString,
+    }
 }
 
 type FnArgList = CommaDelimitedVec<FnArg>;
@@ -125,6 +131,89 @@ fn test_fn_ret() {
     result.unwrap();
 }
 
+/// Arguments of `#[export_function(category = "...", description = "...")]`.
+#[derive(Default)]
+pub struct ExportAttrs {
+    pub category: Option<String>,
+    pub description: Option<String>,
+}
+
+impl ExportAttrs {
+    pub fn parse(tokens: &mut TokenIter) -> std::result::Result<Self, String> {
+        let args = CommaDelimitedVec::<AttrArg>::parse_all(tokens)
+            .map_err(|_| "expected `category = \"...\"` or `description = \"...\"`")?;
+        let mut attrs = Self::default();
+        for arg in args.into_iter().map(|arg| arg.value) {
+            let key = arg.key.to_string();
+            let field = match key.as_str() {
+                "category" => &mut attrs.category,
+                "description" => &mut attrs.description,
+                _ => return Err(format!("unknown argument `{key}`")),
+            };
+            let value = unescape(arg.value.as_str())
+                .ok_or_else(|| format!("unsupported escape in `{key}`"))?;
+            if field.replace(value).is_some() {
+                return Err(format!("duplicate argument `{key}`"));
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// Resolves the escapes of a string literal without its quotes.
+fn unescape(literal: &str) -> Option<String> {
+    let mut value = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            c @ ('\\' | '\'' | '"') => value.push(c),
+            'x' => {
+                let code = chars.by_ref().take(2).collect::<String>();
+                value.push(
+                    u8::from_str_radix(&code, 16)
+                        .ok()
+                        .filter(u8::is_ascii)?
+                        .into(),
+                );
+            }
+            'u' => {
+                let code = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                let code = u32::from_str_radix(code.strip_prefix('{')?, 16).ok()?;
+                value.push(char::from_u32(code)?);
+            }
+            '\n' => {
+                chars = chars.as_str().trim_start().chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[test]
+fn test_export_attrs() {
+    let mut tokens = TokenIter::new(
+        quote! {category = "Math/Vectors", description = "Splits \"v\"\ninto x\u{2192}y"}
+            .into_iter(),
+    );
+
+    let attrs = ExportAttrs::parse(&mut tokens).unwrap();
+
+    assert_eq!(attrs.category.as_deref(), Some("Math/Vectors"));
+    assert_eq!(
+        attrs.description.as_deref(),
+        Some("Splits \"v\"\ninto x\u{2192}y")
+    );
+}
+
 type TupleParser = ParenthesisGroupContaining<CommaDelimitedVec<VerbatimUntil<Comma>>>;
 
 #[derive(Default)]
@@ -1,28 +1,255 @@
 use std::path::PathBuf;
 
 #[derive(clap::Parser, Debug)]
+#[allow(clippy::large_enum_variant)] // parsed once
 pub enum RootArgs {
     #[command(about = "Builds a Rust project.")]
     Build(BuildArgs),
+    #[command(about = "Rebuilds a Rust project whenever its sources change.")]
+    Watch(WatchArgs),
     #[command(about = "Weaves the specified WebAssembly file.")]
     Weave(WeaveArgs),
+    #[command(about = "Prints the woven module as WebAssembly text.")]
+    Wat(WatArgs),
+    #[command(about = "Runs the init function of a woven module and bakes its state into it.")]
+    Snapshot(SnapshotArgs),
+    #[command(about = "Lists export and import changes between two modules.")]
+    Diff(DiffArgs),
+    #[command(about = "Lists the exports of a WebAssembly file as they appear after weaving.")]
+    Inspect(InspectArgs),
 }
 
 #[derive(clap::Args, Debug)]
 pub struct BuildArgs {
     #[arg(short, long)]
     pub project: Option<String>,
+    /// Builds all packages in the workspace, requires `--out-dir`.
+    #[arg(long, conflicts_with = "output")]
+    pub workspace: bool,
     #[arg(short, long)]
     pub feature: Vec<String>,
-    #[arg(short, long)]
-    pub output: PathBuf,
+    #[arg(
+        short,
+        long,
+        required_unless_present = "out_dir",
+        conflicts_with = "out_dir"
+    )]
+    pub output: Option<PathBuf>,
+    /// Weaves every cdylib into `<DIR>/<crate>.wasm`.
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+    /// Cargo profile to build with [default: release]
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Target triple, e.g. `wasm32v1-none` or `wasm32-wasip1` [default: wasm32-unknown-unknown]
+    #[arg(long)]
+    pub target: Option<String>,
+    /// Directory for all generated artifacts.
+    #[arg(long)]
+    pub target_dir: Option<PathBuf>,
+    /// Path to the Cargo.toml of the project or workspace.
+    #[arg(long)]
+    pub manifest_path: Option<PathBuf>,
+    /// Rebuilds the standard library (`-Z build-std`, requires nightly).
+    /// Optionally takes a comma-separated list of crates.
+    #[arg(long, num_args = 0..=1, require_equals = true, value_delimiter = ',')]
+    pub build_std: Option<Vec<String>>,
+    /// Target features added to `target.<triple>.rustflags`, or to `RUSTFLAGS` or
+    /// `CARGO_ENCODED_RUSTFLAGS` if set, e.g. `+multivalue,+reference-types,+simd128`.
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub target_feature: Vec<String>,
+    /// Format of diagnostics and weaver events.
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
+    #[command(flatten)]
+    pub options: WeaveOptions,
     #[arg(last = true)]
     pub cargo_args: Vec<String>,
 }
 
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    /// Milliseconds to wait for further changes before rebuilding.
+    #[arg(long, default_value_t = 300)]
+    pub debounce: u64,
+    #[command(flatten)]
+    pub build: BuildArgs,
+}
+
 #[derive(clap::Args, Debug)]
 pub struct WeaveArgs {
+    /// WebAssembly binary or text.
     pub input: PathBuf,
     #[arg(short, long)]
     pub output: PathBuf,
+    #[command(flatten)]
+    pub options: WeaveOptions,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WeaveOptions {
+    /// How to report exports which exceed the limits of the Resonite host.
+    #[arg(long, value_enum, default_value_t = LintLevel::Warn)]
+    pub lint: LintLevel,
+    /// Fails if the host manifest (`LibFrooxEngine.json`) doesn't define an import,
+    /// regardless of `--lint`.
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Removes all custom sections, including names and producers.
+    #[arg(long)]
+    pub strip: bool,
+    /// Keeps DWARF debug info and remaps its code addresses.
+    /// Otherwise, it is removed since weaving invalidates it.
+    #[arg(long, conflicts_with = "strip")]
+    pub debug_info: bool,
+    /// Keeps exports matching the glob in addition to the memory and `#[export_function]`s.
+    #[arg(long, value_name = "GLOB", value_parser = glob::Pattern::new)]
+    pub allow_export: Vec<glob::Pattern>,
+    /// Removes exports matching the glob.
+    #[arg(long, value_name = "GLOB", value_parser = glob::Pattern::new)]
+    pub deny_export: Vec<glob::Pattern>,
+    /// Prepended to the names of exported functions.
+    #[arg(long, default_value = "")]
+    pub export_prefix: String,
+    /// JSON object mapping export names to new names.
+    #[arg(long, value_name = "FILE")]
+    pub rename_exports: Option<PathBuf>,
+    /// Traps after about this many instructions per call, see `set_fuel_budget` to change it.
+    #[arg(
+        long,
+        value_name = "BUDGET",
+        value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64)
+    )]
+    pub fuel: Option<u64>,
+    /// Traps if calls nest deeper than this, see `max_call_depth_reached` for the deepest call.
+    #[arg(long, value_name = "DEPTH")]
+    pub max_call_depth: Option<u32>,
+    /// Makes NaN bit patterns identical on every CPU, for values synced between clients.
+    #[arg(long)]
+    pub canonicalize_nans: bool,
+    /// Removes functions, globals, imports and types that are unreachable from the exports.
+    #[arg(long)]
+    pub dce: bool,
+    /// Replaces the imports of a host module with pure WebAssembly functions, so the module
+    /// runs without the Resonite host.
+    #[arg(long, value_enum, value_name = "MODULE")]
+    pub polyfill: Vec<PolyfillModule>,
+    /// Writes a JSON description of the exported functions for building ProtoFlux nodes.
+    #[arg(long, value_name = "FILE")]
+    pub nodes: Option<PathBuf>,
+    /// Embeds the description of the exported functions as a `frooxengine.nodes` section.
+    #[arg(long)]
+    pub embed_nodes: bool,
+    /// Format of the output file.
+    #[arg(long, value_enum, default_value_t = Emit::Wasm)]
+    pub emit: Emit,
+    #[command(flatten)]
+    pub print: PrintArgs,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// WebAssembly binary.
+    Wasm,
+    /// WebAssembly text, e.g. for the `TextWebAssemblyModule` component.
+    Wat,
+}
+
+impl Emit {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Emit::Wasm => "wasm",
+            Emit::Wat => "wat",
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolyfillModule {
+    /// Trigonometric, hyperbolic, exponential and logarithmic functions.
+    Math,
+    /// WASI for `wasm32-wasip1`, discarding output and returning deterministic time and randomness.
+    Wasi,
+}
+
+impl From<PolyfillModule> for wasm_weaver::polyfill::Polyfill {
+    fn from(value: PolyfillModule) -> Self {
+        match value {
+            PolyfillModule::Math => Self::Math,
+            PolyfillModule::Wasi => Self::Wasi,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PrintArgs {
+    /// Prints instructions as folded S-expressions.
+    #[arg(long)]
+    pub fold: bool,
+    /// Keeps names from the name section.
+    #[arg(long)]
+    pub names: bool,
+}
+
+impl PrintArgs {
+    pub fn options(&self) -> wasm_weaver::text::PrintOptions {
+        wasm_weaver::text::PrintOptions {
+            fold: self.fold,
+            names: self.names,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Don't check exports.
+    Allow,
+    /// Print a warning for each violation.
+    Warn,
+    /// Fail without writing the output.
+    Deny,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Rendered compiler diagnostics and a summary of each woven artifact.
+    Human,
+    /// Cargo's JSON messages and weaver events, one JSON object per line.
+    Json,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WatArgs {
+    /// WebAssembly binary or text.
+    pub input: PathBuf,
+    /// Writes to a file instead of stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub print: PrintArgs,
+}
+
+/// Calls to `math` imports use the Rust implementation, which may differ in the last bits.
+/// Other host imports trap.
+#[derive(clap::Args, Debug)]
+pub struct SnapshotArgs {
+    /// Woven WebAssembly binary or text.
+    pub input: PathBuf,
+    /// Exported function without parameters to run, removed from the output.
+    #[arg(long, value_name = "EXPORT")]
+    pub init: String,
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+/// Exits with an error if a change breaks existing ProtoFlux graphs or hosts.
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    pub old: PathBuf,
+    pub new: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    pub input: PathBuf,
 }
@@ -1,34 +1,119 @@
 use std::{
     error::Error,
-    io::BufRead,
+    io::{BufRead, BufReader, IsTerminal},
     path::PathBuf,
     process::{Command, Stdio},
 };
 
 use serde::Deserialize;
 
-use crate::args::BuildArgs;
+use crate::{
+    args::{BuildArgs, MessageFormat},
+    report,
+};
+
+/// Build settings from `[package.metadata.wasm-weaver]` or `[workspace.metadata.wasm-weaver]`.
+/// Command line arguments take precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildConfig {
+    pub profile: Option<String>,
+    pub target: Option<String>,
+    /// Crates for `-Z build-std`, empty for the default set.
+    pub build_std: Option<Vec<String>>,
+    #[serde(default)]
+    pub target_features: Vec<String>,
+}
+
+/// A cdylib built by cargo.
+#[derive(Debug)]
+pub struct Artifact {
+    /// Name of the library target.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct BuildOutput {
+    pub artifacts: Vec<Artifact>,
+    pub success: bool,
+}
+
+pub fn build(args: &BuildArgs) -> Result<BuildOutput, Box<dyn Error>> {
+    let config = read_config(args)?;
 
-pub fn build(args: &BuildArgs) -> Result<Option<PathBuf>, Box<dyn Error>> {
     let mut command = Command::new("cargo");
     command.arg("build");
 
+    if let Some(manifest_path) = &args.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+
     if let Some(project) = &args.project {
         command.arg("--package").arg(project);
     }
 
+    if args.workspace {
+        command.arg("--workspace");
+    }
+
     if !args.feature.is_empty() {
         command.arg("--features").arg(args.feature.join(","));
     }
 
-    command.args([
-        "--lib",
-        "--target",
-        "wasm32-unknown-unknown",
-        "--release",
-        "--message-format",
-        "json-render-diagnostics",
-    ]);
+    let profile = args.profile.as_ref().or(config.profile.as_ref());
+    let target = (args.target.as_ref().or(config.target.as_ref()))
+        .map_or("wasm32-unknown-unknown", String::as_str);
+    command
+        .arg("--lib")
+        .arg("--target")
+        .arg(target)
+        .arg("--profile")
+        .arg(profile.map_or("release", String::as_str))
+        .arg("--message-format")
+        .arg(match args.message_format {
+            MessageFormat::Json => "json",
+            // Diagnostics are rendered by us to count them
+            MessageFormat::Human if std::io::stderr().is_terminal() => {
+                "json-diagnostic-rendered-ansi"
+            }
+            MessageFormat::Human => "json",
+        });
+
+    if let Some(target_dir) = &args.target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+
+    if let Some(crates) = args.build_std.as_ref().or(config.build_std.as_ref()) {
+        match crates.is_empty() {
+            true => command.arg("-Zbuild-std"),
+            false => command.arg(format!("-Zbuild-std={}", crates.join(","))),
+        };
+    }
+
+    let target_features = match args.target_feature.is_empty() {
+        true => &config.target_features,
+        false => &args.target_feature,
+    };
+    if !target_features.is_empty() {
+        let flag = format!("-Ctarget-feature={}", target_features.join(","));
+        // Cargo ignores the rustflags of its config if one of these variables is set
+        if let Ok(encoded) = std::env::var("CARGO_ENCODED_RUSTFLAGS") {
+            let separator = if encoded.is_empty() { "" } else { "\x1f" };
+            command.env(
+                "CARGO_ENCODED_RUSTFLAGS",
+                format!("{encoded}{separator}{flag}"),
+            );
+        } else if let Ok(rustflags) = std::env::var("RUSTFLAGS") {
+            command.env("RUSTFLAGS", format!("{rustflags} {flag}"));
+        } else {
+            // Merged with `target.<triple>.rustflags` of the config, but `build.rustflags`
+            // no longer applies
+            command
+                .arg("--config")
+                .arg(format!("target.\"{target}\".rustflags = [\"{flag}\"]"));
+        }
+    }
 
     command.args(&args.cargo_args);
 
@@ -37,40 +122,169 @@ pub fn build(args: &BuildArgs) -> Result<Option<PathBuf>, Box<dyn Error>> {
         .stdout(Stdio::piped())
         .stdin(Stdio::null());
 
-    let output = command.output()?;
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("failed to run cargo: {e}"))?;
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
 
-    let mut wasm = None;
-    for line in output.stdout.lines() {
-        let message: CargoMessage = serde_json::from_str(&line?)?;
+    let mut artifacts = Vec::<Artifact>::new();
+    let mut success = true;
+    let (mut errors, mut warnings) = (0, 0);
+    for line in stdout.lines() {
+        let line = line?;
+        if args.message_format == MessageFormat::Json {
+            println!("{line}");
+        }
+        let message: CargoMessage = serde_json::from_str(&line)?;
         match message {
+            CargoMessage::CompilerMessage(CompilerMessage { message }) => {
+                match message.level {
+                    Severity::Error | Severity::InternalError => errors += 1,
+                    Severity::Warning => warnings += 1,
+                    _ => (),
+                }
+                if args.message_format == MessageFormat::Human
+                    && let Some(rendered) = message.rendered
+                {
+                    eprint!("{rendered}");
+                }
+            }
             CargoMessage::CompilerArtifact(artifact)
-                if artifact
-                    .target
-                    .kind
-                    .iter()
-                    .any(|it| *it == TargetKind::Cdylib) =>
+                if artifact.target.kind.contains(&TargetKind::Cdylib) =>
             {
-                if let Some(found) = artifact
+                let Some(path) = artifact
                     .filenames
                     .into_iter()
                     .find(|file| file.extension().is_some_and(|ext| ext == "wasm"))
-                {
-                    wasm = Some(found);
-                }
+                else {
+                    continue;
+                };
+                // Fresh artifacts may be reported more than once
+                artifacts.retain(|a| a.name != artifact.target.name);
+                artifacts.push(Artifact {
+                    name: artifact.target.name,
+                    path,
+                });
             }
-            CargoMessage::BuildFinished { success: false } => return Ok(None),
-            CargoMessage::BuildFinished { success: true } => break,
+            CargoMessage::BuildFinished { success: finished } => success &= finished,
             _ => continue,
         }
     }
+    success &= child.wait()?.success();
+
+    if args.message_format == MessageFormat::Human && errors + warnings > 0 {
+        let counts = [(errors, "error"), (warnings, "warning")]
+            .into_iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, noun)| report::count(n, noun))
+            .collect::<Vec<_>>();
+        let level = if errors > 0 { "error" } else { "warning" };
+        eprintln!("{level}: build emitted {}", counts.join(" and "));
+    }
+
+    Ok(BuildOutput { artifacts, success })
+}
+
+/// Directories to watch for changes and the directory with the build output.
+pub struct WatchPaths {
+    pub sources: Vec<PathBuf>,
+    pub target_dir: PathBuf,
+}
+
+/// Watches all workspace members, which covers path dependencies within the workspace.
+pub fn watch_paths(args: &BuildArgs) -> Result<WatchPaths, Box<dyn Error>> {
+    let metadata = read_metadata(args)?;
+    let sources = metadata
+        .packages
+        .iter()
+        .filter_map(|p| p.manifest_path.parent())
+        .map(PathBuf::from)
+        .collect();
+    let target_dir = match &args.target_dir {
+        Some(target_dir) => std::path::absolute(target_dir)?,
+        None => metadata.target_directory,
+    };
+    Ok(WatchPaths {
+        sources,
+        target_dir,
+    })
+}
+
+fn read_metadata(args: &BuildArgs) -> Result<CargoMetadata, Box<dyn Error>> {
+    let mut command = Command::new("cargo");
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(manifest_path) = &args.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run cargo: {e}"))?;
+    if !output.status.success() {
+        return Err("cargo metadata failed".into());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Reads the build settings of the selected package, falling back to the workspace.
+fn read_config(args: &BuildArgs) -> Result<BuildConfig, Box<dyn Error>> {
+    let metadata = read_metadata(args)?;
+
+    let package = match (&args.project, &args.manifest_path) {
+        _ if args.workspace => None,
+        (Some(project), _) => metadata.packages.iter().find(|p| &p.name == project),
+        (None, Some(manifest_path)) => {
+            let manifest_path = manifest_path.canonicalize()?;
+            metadata
+                .packages
+                .iter()
+                .find(|p| p.manifest_path == manifest_path)
+        }
+        // The innermost package containing the working directory
+        (None, None) => {
+            let current_dir = std::env::current_dir()?;
+            metadata
+                .packages
+                .iter()
+                .filter(|p| {
+                    p.manifest_path
+                        .parent()
+                        .is_some_and(|dir| current_dir.starts_with(dir))
+                })
+                .max_by_key(|p| p.manifest_path.as_os_str().len())
+        }
+    };
+
+    let config = package
+        .and_then(|p| p.metadata.as_ref()?.wasm_weaver.clone())
+        .or_else(|| metadata.metadata?.wasm_weaver);
+    Ok(config.unwrap_or_default())
+}
 
-    Ok(wasm)
+#[derive(Debug, Deserialize)]
+struct CargoMetadata {
+    packages: Vec<Package>,
+    target_directory: PathBuf,
+    metadata: Option<WeaverMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    metadata: Option<WeaverMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeaverMetadata {
+    #[serde(rename = "wasm-weaver")]
+    wasm_weaver: Option<BuildConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", tag = "reason")]
 enum CargoMessage {
-    // CompilerMessage(CompilerMessage),
+    CompilerMessage(CompilerMessage),
     CompilerArtifact(CompilerArtifact),
     BuildFinished {
         success: bool,
@@ -79,12 +293,18 @@ enum CargoMessage {
     Other,
 }
 
-// #[derive(Debug, Deserialize)]
-// struct CompilerMessage {
-//     package_id: PathBuf,
-//     manifest_path: PathBuf,
-//     message: Diagnostic,
-// }
+#[derive(Debug, Deserialize)]
+struct CompilerMessage {
+    // package_id: PathBuf,
+    // manifest_path: PathBuf,
+    message: Diagnostic,
+}
+
+#[derive(Debug, Deserialize)]
+struct Diagnostic {
+    level: Severity,
+    rendered: Option<String>,
+}
 
 #[derive(Debug, Deserialize)]
 struct CompilerArtifact {
@@ -96,6 +316,7 @@ struct CompilerArtifact {
 
 #[derive(Debug, Deserialize)]
 struct Target {
+    name: String,
     kind: Vec<TargetKind>,
 }
 
@@ -119,10 +340,12 @@ enum TargetKind {
 #[serde(rename_all = "kebab-case")]
 enum Severity {
     Error,
-    Warming,
+    Warning,
     Note,
     Help,
-    FailureNode,
+    FailureNote,
     #[serde(rename = "error: internal compiler error")]
     InternalError,
+    #[serde(other)]
+    Other,
 }
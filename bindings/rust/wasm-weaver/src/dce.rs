@@ -0,0 +1,199 @@
+//! Finds the functions and globals reachable from the kept exports, the start function,
+//! element segments and the initializers of tables and data segments.
+
+use wasmparser::{
+    ConstExpr, DataKind, ElementItems, ElementKind, Export, ExternalKind, Operator, TableInit,
+};
+
+use crate::{parse::ParsedModule, weaver::WeaveError};
+
+/// Whether a function or global is reachable, by index including imports.
+#[derive(Debug)]
+pub struct Live {
+    pub functions: Box<[bool]>,
+    pub globals: Box<[bool]>,
+}
+
+/// What dead-code elimination removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DceStats {
+    /// Functions, including imports
+    pub functions: usize,
+    /// Globals, including imports
+    pub globals: usize,
+    /// Size of the removed function bodies in the input
+    pub code_bytes: usize,
+}
+
+impl Live {
+    pub fn stats(&self, parsed: &ParsedModule) -> DceStats {
+        let first_body = self.functions.len() - parsed.code.len();
+        let code_bytes = (parsed.code.iter().enumerate())
+            .filter(|(i, _)| !self.functions[first_body + i])
+            .map(|(i, body)| body.range().end - parsed.code_entry_start(i as u32))
+            .sum();
+        DceStats {
+            functions: self.functions.iter().filter(|live| !**live).count(),
+            globals: self.globals.iter().filter(|live| !**live).count(),
+            code_bytes,
+        }
+    }
+}
+
+enum Item {
+    Function(u32),
+    Global(u32),
+}
+
+pub fn find_live<'e>(
+    parsed: &ParsedModule,
+    exports: impl IntoIterator<Item = &'e Export<'e>>,
+    start: Option<u32>,
+) -> Result<Live, WeaveError> {
+    let mut imported_functions = 0;
+    let mut imported_globals = 0;
+    for import in &parsed.imports {
+        match import.ty {
+            wasmparser::TypeRef::Func(_) => imported_functions += 1,
+            wasmparser::TypeRef::Global(_) => imported_globals += 1,
+            _ => (),
+        }
+    }
+    let mut live = Live {
+        functions: vec![false; imported_functions + parsed.code.len()].into(),
+        globals: vec![false; imported_globals + parsed.globals.len()].into(),
+    };
+
+    let mut queue = Vec::new();
+    for export in exports {
+        match export.kind {
+            ExternalKind::Func => queue.push(Item::Function(export.index)),
+            ExternalKind::Global => queue.push(Item::Global(export.index)),
+            _ => (),
+        }
+    }
+    queue.extend(start.map(Item::Function));
+    for element in &parsed.elements {
+        if let ElementKind::Active { offset_expr, .. } = &element.kind {
+            const_expr_items(offset_expr, &mut queue)?;
+        }
+        match &element.items {
+            ElementItems::Functions(functions) => {
+                for function in functions.clone() {
+                    queue.push(Item::Function(function?));
+                }
+            }
+            ElementItems::Expressions(_, exprs) => {
+                for expr in exprs.clone() {
+                    const_expr_items(&expr?, &mut queue)?;
+                }
+            }
+        }
+    }
+    for table in &parsed.tables {
+        if let TableInit::Expr(expr) = &table.init {
+            const_expr_items(expr, &mut queue)?;
+        }
+    }
+    for datum in &parsed.data {
+        if let DataKind::Active { offset_expr, .. } = &datum.kind {
+            const_expr_items(offset_expr, &mut queue)?;
+        }
+    }
+
+    while let Some(item) = queue.pop() {
+        match item {
+            Item::Function(index) => {
+                let Some(is_live) = live.functions.get_mut(index as usize) else {
+                    return Err(WeaveError::FunctionIndexOutOfBounds(index));
+                };
+                if std::mem::replace(is_live, true) {
+                    continue;
+                }
+                let Some(body) = (index as usize)
+                    .checked_sub(imported_functions)
+                    .and_then(|body| parsed.code.get(body))
+                else {
+                    continue;
+                };
+                for op in body.get_operators_reader()? {
+                    match op? {
+                        Operator::Call { function_index }
+                        | Operator::ReturnCall { function_index }
+                        | Operator::RefFunc { function_index } => {
+                            queue.push(Item::Function(function_index));
+                        }
+                        Operator::GlobalGet { global_index }
+                        | Operator::GlobalSet { global_index } => {
+                            queue.push(Item::Global(global_index));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            Item::Global(index) => {
+                let Some(is_live) = live.globals.get_mut(index as usize) else {
+                    return Err(WeaveError::GlobalIndexOutOfBounds(index));
+                };
+                if std::mem::replace(is_live, true) {
+                    continue;
+                }
+                if let Some(global) = (index as usize)
+                    .checked_sub(imported_globals)
+                    .and_then(|global| parsed.globals.get(global))
+                {
+                    const_expr_items(&global.init_expr, &mut queue)?;
+                }
+            }
+        }
+    }
+
+    Ok(live)
+}
+
+fn const_expr_items(expr: &ConstExpr<'_>, queue: &mut Vec<Item>) -> Result<(), WeaveError> {
+    for op in expr.get_operators_reader() {
+        match op? {
+            Operator::RefFunc { function_index } => queue.push(Item::Function(function_index)),
+            Operator::GlobalGet { global_index } => queue.push(Item::Global(global_index)),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_live_items() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f32" (func $sin (param f32) (result f32)))
+            (import "math" "cos_f32" (func $cos (param f32) (result f32)))
+            (global $used (mut i32) (i32.const 0))
+            (global $unused i32 (i32.const 0))
+            (global $init funcref (ref.func $referenced))
+            (func (export "foo") (param f32) (result f32)
+                i32.const 1
+                global.set $used
+                global.get $init
+                drop
+                local.get 0
+                call $sin)
+            (func $referenced)
+            (func $unreachable (result f32)
+                f32.const 0
+                call $cos))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+
+    let live = find_live(&parsed, &parsed.exports, parsed.start).unwrap();
+    assert_eq!(*live.functions, [true, false, true, true, false]);
+    assert_eq!(*live.globals, [true, false, true]);
+
+    // $unreachable: size, no locals, f32.const 0, call $cos, end
+    let stats = live.stats(&parsed);
+    assert_eq!(
+        (stats.functions, stats.globals, stats.code_bytes),
+        (2, 1, 10)
+    );
+}
@@ -0,0 +1,649 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use gimli::{
+    EndianSlice, LittleEndian, SectionId,
+    read::{self, Reader},
+    write::{
+        self, Address, AttributeValue, ConvertError, EndianVec, LineProgram, LineString, Location,
+        LocationList, RangeList,
+    },
+};
+use wasm_encoder::CustomSection;
+use wasmparser::CustomSectionReader;
+
+/// Maps code section offsets of the input module to offsets in the woven module.
+///
+/// Offsets are relative to the start of the code section contents,
+/// which is how DWARF addresses code in WebAssembly.
+#[derive(Debug, Default)]
+pub struct AddressMap {
+    offsets: BTreeMap<u64, u64>,
+    reverse: BTreeMap<u64, u64>,
+    /// Ranges of removed functions by start
+    discarded: BTreeMap<u64, u64>,
+}
+
+/// The address of code that was removed, as used by LLVM for WebAssembly.
+const TOMBSTONE: u64 = 0xffff_ffff;
+
+impl AddressMap {
+    pub fn insert(&mut self, old: u64, new: u64) {
+        self.offsets.insert(old, new);
+        self.reverse.insert(new, old);
+    }
+
+    /// Marks the code of a removed function, its addresses are mapped to a tombstone.
+    pub fn discard(&mut self, old: Range<u64>) {
+        self.discarded.insert(old.start, old.end);
+    }
+
+    /// Maps an address to the closest preceding known offset, keeping the distance to it.
+    /// Returns `None` for addresses outside of the code.
+    pub fn map(&self, address: u64) -> Option<u64> {
+        if let Some((_, end)) = self.discarded.range(..=address).next_back()
+            && address < *end
+        {
+            return Some(TOMBSTONE);
+        }
+        let (end, _) = self.offsets.last_key_value()?;
+        if address > *end {
+            return None;
+        }
+        let (old, new) = self.offsets.range(..=address).next_back()?;
+        Some(new + (address - old))
+    }
+
+    /// Maps a woven address back to the input module.
+    fn unmap(&self, address: u64) -> Option<u64> {
+        let (new, old) = self.reverse.range(..=address).next_back()?;
+        Some(old + (address - new))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DebugInfoError {
+    #[error("failed to read DWARF: {0}")]
+    Read(#[from] gimli::Error),
+    #[error("failed to convert DWARF: {0}")]
+    Convert(#[from] ConvertError),
+    #[error("failed to write DWARF: {0}")]
+    Write(#[from] gimli::write::Error),
+}
+
+pub fn is_debug_section(section: &CustomSectionReader) -> bool {
+    section.name().starts_with(".debug_")
+}
+
+/// Re-encodes the `.debug_*` sections with all code addresses remapped.
+pub fn rewrite(
+    sections: &[CustomSectionReader],
+    address_map: &AddressMap,
+) -> Result<Vec<CustomSection<'static>>, DebugInfoError> {
+    let load = |id: SectionId| -> Result<_, gimli::Error> {
+        let data = sections
+            .iter()
+            .find(|section| section.name() == id.name())
+            .map(|section| section.data())
+            .unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let dwarf = gimli::read::Dwarf::load(load)?;
+
+    // Addresses outside of the code are tombstones of discarded functions, keep them
+    let convert_address = |address| {
+        Some(Address::Constant(
+            address_map.map(address).unwrap_or(address),
+        ))
+    };
+    let mut converted = write::Dwarf::from(&dwarf, &convert_address)?;
+
+    // Line programs and function lengths are converted as offsets relative
+    // to a start address, but the distances change as well.
+    let mut units = dwarf.units();
+    let mut index = 0;
+    while let Some(header) = units.next()? {
+        let id = converted.units.id(index);
+        index += 1;
+        let unit = converted.units.get_mut(id);
+        remap_high_pc(unit, unit.root(), address_map);
+        remap_lists(unit, address_map);
+        if let Some(program) = dwarf.unit(header)?.line_program {
+            unit.line_program = remap_line_program(
+                program,
+                &dwarf,
+                &mut converted.line_strings,
+                &mut converted.strings,
+                address_map,
+            )?;
+        }
+    }
+
+    let mut out = write::Sections::new(EndianVec::new(LittleEndian));
+    converted.write(&mut out)?;
+
+    let mut custom = Vec::new();
+    out.for_each(|id, data| {
+        if !data.slice().is_empty() {
+            custom.push(CustomSection {
+                name: id.name().into(),
+                data: data.slice().to_vec().into(),
+            });
+        }
+        Ok::<_, gimli::write::Error>(())
+    })?;
+
+    Ok(custom)
+}
+
+/// Fixes `DW_AT_high_pc` attributes which are lengths instead of addresses.
+fn remap_high_pc(unit: &mut write::Unit, entry: write::UnitEntryId, address_map: &AddressMap) {
+    let die = unit.get_mut(entry);
+    if let Some(AttributeValue::Address(Address::Constant(low))) = die.get(gimli::DW_AT_low_pc) {
+        let low = *low;
+        let length = match die.get(gimli::DW_AT_high_pc) {
+            Some(AttributeValue::Udata(length)) => Some(*length),
+            Some(AttributeValue::Data4(length)) => Some(u64::from(*length)),
+            Some(AttributeValue::Data8(length)) => Some(*length),
+            _ => None,
+        };
+        if let Some(length) = length.and_then(|length| remap_length(address_map, low, length)) {
+            die.set(gimli::DW_AT_high_pc, AttributeValue::Udata(length));
+        }
+    }
+    let children = die.children().copied().collect::<Vec<_>>();
+    for child in children {
+        remap_high_pc(unit, child, address_map);
+    }
+}
+
+/// Maps the length of the code starting at a woven address.
+fn remap_length(address_map: &AddressMap, begin: u64, length: u64) -> Option<u64> {
+    if begin == TOMBSTONE {
+        return None;
+    }
+    let old_begin = address_map.unmap(begin)?;
+    let end = address_map.map(old_begin + length)?;
+    end.checked_sub(begin)
+}
+
+/// Fixes the range and location lists. gimli keeps offset pairs and the lengths of start and
+/// length entries, and only maps the addresses. The lists are re-added to new tables.
+fn remap_lists(unit: &mut write::Unit, address_map: &AddressMap) {
+    // Offset pairs are relative to the unit's low PC unless a list sets a base address
+    let base = match unit.get(unit.root()).get(gimli::DW_AT_low_pc) {
+        Some(AttributeValue::Address(Address::Constant(low))) => *low,
+        _ => 0,
+    };
+    // Before DWARF 5, gimli reads offset pairs as addresses, which are only correct for a base
+    // address of 0 as emitted by LLVM for WebAssembly
+    let offset_pairs = unit.version() >= 5;
+    let ranges = std::mem::take(&mut unit.ranges);
+    let locations = std::mem::take(&mut unit.locations);
+
+    let mut queue = vec![unit.root()];
+    while let Some(entry) = queue.pop() {
+        let die = unit.get(entry);
+        queue.extend(die.children());
+        let attrs = die
+            .attrs()
+            .map(|attr| (attr.name(), attr.get().clone()))
+            .collect::<Vec<_>>();
+        for (name, value) in attrs {
+            let value = match value {
+                AttributeValue::RangeListRef(id) => {
+                    let mut base = base;
+                    let list = (ranges.get(id).0.iter())
+                        .filter_map(|range| {
+                            remap_range(range.clone(), &mut base, offset_pairs, address_map)
+                        })
+                        .collect();
+                    AttributeValue::RangeListRef(unit.ranges.add(RangeList(list)))
+                }
+                AttributeValue::LocationListRef(id) => {
+                    let mut base = base;
+                    let list = (locations.get(id).0.iter())
+                        .filter_map(|location| {
+                            remap_location(location.clone(), &mut base, offset_pairs, address_map)
+                        })
+                        .collect();
+                    AttributeValue::LocationListRef(unit.locations.add(LocationList(list)))
+                }
+                _ => continue,
+            };
+            unit.get_mut(entry).set(name, value);
+        }
+    }
+}
+
+/// Remaps the bounds of an offset pair relative to the woven `base`.
+/// Returns `None` for code that was removed.
+fn remap_offsets(address_map: &AddressMap, base: u64, begin: u64, end: u64) -> Option<(u64, u64)> {
+    let old_base = match base {
+        // Addresses before the code are not mapped
+        0 => 0,
+        TOMBSTONE => return None,
+        base => address_map.unmap(base)?,
+    };
+    let begin = address_map.map(old_base + begin)?;
+    let end = address_map.map(old_base + end)?;
+    if begin == TOMBSTONE || end == TOMBSTONE {
+        return None;
+    }
+    Some((begin.checked_sub(base)?, end.checked_sub(base)?))
+}
+
+fn remap_range(
+    range: write::Range,
+    base: &mut u64,
+    offset_pairs: bool,
+    address_map: &AddressMap,
+) -> Option<write::Range> {
+    Some(match range {
+        write::Range::BaseAddress {
+            address: Address::Constant(address),
+        } => {
+            *base = address;
+            range
+        }
+        write::Range::OffsetPair { begin, end } if offset_pairs => {
+            let (begin, end) = remap_offsets(address_map, *base, begin, end)?;
+            write::Range::OffsetPair { begin, end }
+        }
+        write::Range::StartLength {
+            begin: Address::Constant(begin),
+            length,
+        } => write::Range::StartLength {
+            begin: Address::Constant(begin),
+            length: remap_length(address_map, begin, length).unwrap_or(length),
+        },
+        range => range,
+    })
+}
+
+fn remap_location(
+    location: Location,
+    base: &mut u64,
+    offset_pairs: bool,
+    address_map: &AddressMap,
+) -> Option<Location> {
+    Some(match location {
+        Location::BaseAddress {
+            address: Address::Constant(address),
+        } => {
+            *base = address;
+            location
+        }
+        Location::OffsetPair { begin, end, data } if offset_pairs => {
+            let (begin, end) = remap_offsets(address_map, *base, begin, end)?;
+            Location::OffsetPair { begin, end, data }
+        }
+        Location::StartLength {
+            begin: Address::Constant(begin),
+            length,
+            data,
+        } => Location::StartLength {
+            begin: Address::Constant(begin),
+            length: remap_length(address_map, begin, length).unwrap_or(length),
+            data,
+        },
+        location => location,
+    })
+}
+
+/// Converts a line program like [`LineProgram::from`], but maps the address of every row.
+fn remap_line_program<R: Reader<Offset = usize>>(
+    from_program: read::IncompleteLineProgram<R>,
+    dwarf: &read::Dwarf<R>,
+    line_strings: &mut write::LineStringTable,
+    strings: &mut write::StringTable,
+    address_map: &AddressMap,
+) -> Result<LineProgram, DebugInfoError> {
+    let mut line_string = |attr: read::AttributeValue<R>| -> Result<_, DebugInfoError> {
+        Ok(match attr {
+            read::AttributeValue::String(r) => LineString::String(r.to_slice()?.to_vec()),
+            read::AttributeValue::DebugStrRef(offset) => {
+                let r = dwarf.debug_str.get_str(offset)?;
+                LineString::StringRef(strings.add(r.to_slice()?))
+            }
+            read::AttributeValue::DebugLineStrRef(offset) => {
+                let r = dwarf.debug_line_str.get_str(offset)?;
+                LineString::LineStringRef(line_strings.add(r.to_slice()?))
+            }
+            _ => return Err(ConvertError::UnsupportedLineStringForm.into()),
+        })
+    };
+
+    let header = from_program.header();
+    let encoding = header.encoding();
+    let empty = || LineString::String(Vec::new());
+
+    let working_dir = match header.directory(0) {
+        Some(dir) => line_string(dir)?,
+        None => empty(),
+    };
+    let (source_dir, source_file) = match header.file(0) {
+        Some(file) => {
+            let source_dir = match file.directory_index() {
+                0 => None,
+                index => Some(line_string(
+                    header
+                        .directory(index)
+                        .ok_or(ConvertError::InvalidDirectoryIndex)?,
+                )?),
+            };
+            (source_dir, line_string(file.path_name())?)
+        }
+        None => (None, empty()),
+    };
+    if header.line_base() > 0 {
+        return Err(ConvertError::InvalidLineBase.into());
+    }
+    let mut program = LineProgram::new(
+        encoding,
+        header.line_encoding(),
+        working_dir,
+        source_dir,
+        source_file,
+        None,
+    );
+
+    // Files must get the same ids as in the units converted by gimli
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    if header.version() <= 4 {
+        dirs.push(program.default_directory());
+        // File index 0 is invalid before DWARF 5
+        files.push(None);
+    }
+    for dir in header.include_directories() {
+        dirs.push(program.add_directory(line_string(dir.clone())?));
+    }
+    program.file_has_timestamp = header.file_has_timestamp();
+    program.file_has_size = header.file_has_size();
+    program.file_has_md5 = header.file_has_md5();
+    program.file_has_source = header.file_has_source();
+    for file in header.file_names() {
+        let name = line_string(file.path_name())?;
+        let dir = *dirs
+            .get(file.directory_index() as usize)
+            .ok_or(ConvertError::InvalidDirectoryIndex)?;
+        let info = write::FileInfo {
+            timestamp: file.timestamp(),
+            size: file.size(),
+            md5: *file.md5(),
+            source: file.source().map(&mut line_string).transpose()?,
+        };
+        files.push(Some(program.add_file(name, dir, Some(info))));
+    }
+
+    let map = |address| address_map.map(address).unwrap_or(address);
+    let mut sequence_start = None;
+    let mut rows = from_program.rows();
+    while let Some((_, row)) = rows.next_row()? {
+        let start = *sequence_start.get_or_insert_with(|| {
+            let start = map(row.address());
+            program.begin_sequence(Some(Address::Constant(start)));
+            start
+        });
+        let offset = map(row.address()).saturating_sub(start);
+        if row.end_sequence() {
+            program.end_sequence(offset);
+            sequence_start = None;
+            continue;
+        }
+        let new_row = program.row();
+        new_row.address_offset = offset;
+        new_row.op_index = row.op_index();
+        new_row.file = files
+            .get(row.file_index() as usize)
+            .copied()
+            .flatten()
+            .ok_or(ConvertError::InvalidFileIndex)?;
+        new_row.line = row.line().map_or(0, |line| line.get());
+        new_row.column = match row.column() {
+            read::ColumnType::LeftEdge => 0,
+            read::ColumnType::Column(column) => column.get(),
+        };
+        new_row.discriminator = row.discriminator();
+        new_row.is_statement = row.is_stmt();
+        new_row.basic_block = row.basic_block();
+        new_row.prologue_end = row.prologue_end();
+        new_row.epilogue_begin = row.epilogue_begin();
+        new_row.isa = row.isa();
+        program.generate_row();
+    }
+
+    Ok(program)
+}
+
+/// Number of bytes of an unsigned LEB128 encoded integer.
+pub fn leb128_len(mut value: u64) -> u64 {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+#[test]
+fn test_line_program_remapped() {
+    use gimli::write::{DwarfUnit, LineProgram, LineString};
+    use wasmparser::Parser;
+
+    use crate::parse::ParsedModule;
+
+    fn op_offsets(parsed: &ParsedModule) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        let mut reader = parsed.code[0].get_operators_reader().unwrap();
+        while !reader.eof() {
+            offsets.push((reader.original_position() - parsed.code_section_start) as u64);
+            reader.read().unwrap();
+        }
+        offsets
+    }
+
+    let mut wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func (export "foo") (param i32)
+                local.get 0
+                call $ret
+                unreachable))"#,
+    )
+    .unwrap();
+    let input_offsets = op_offsets(&ParsedModule::read(Parser::new(0), &wasm).unwrap());
+
+    // One line table row for each instruction
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 4,
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+    let mut program = LineProgram::new(
+        encoding,
+        Default::default(),
+        LineString::String(b"/".to_vec()),
+        None,
+        LineString::String(b"lib.rs".to_vec()),
+        None,
+    );
+    let dir = program.default_directory();
+    let file = program.add_file(LineString::String(b"lib.rs".to_vec()), dir, None);
+    program.begin_sequence(Some(Address::Constant(input_offsets[0])));
+    for (line, offset) in input_offsets.iter().enumerate() {
+        program.row().file = file;
+        program.row().address_offset = offset - input_offsets[0];
+        program.row().line = line as u64 + 1;
+        program.generate_row();
+    }
+    program.end_sequence(input_offsets[2] + 1 - input_offsets[0]);
+    dwarf.unit.line_program = program;
+    append_dwarf(&mut wasm, &mut dwarf);
+
+    let woven = weave_with_debug_info(&wasm);
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    let output_offsets = op_offsets(&parsed);
+
+    let dwarf = load_dwarf(&parsed);
+    let unit = dwarf.units().next().unwrap().unwrap();
+    let unit = dwarf.unit(unit).unwrap();
+    let mut rows = unit.line_program.unwrap().rows();
+    let mut addresses = Vec::new();
+    while let Some((_, row)) = rows.next_row().unwrap() {
+        if !row.end_sequence() {
+            addresses.push(row.address());
+        }
+    }
+
+    // The marker call shrinks from 2 bytes to a 1 byte return
+    assert_eq!(output_offsets[2], input_offsets[2] - 1);
+    assert_eq!(addresses, output_offsets);
+}
+
+#[test]
+fn test_range_lists_remapped() {
+    use gimli::write::{DwarfUnit, Expression};
+    use wasmparser::Parser;
+
+    use crate::parse::ParsedModule;
+
+    fn code_range(parsed: &ParsedModule) -> (u64, u64) {
+        let body = &parsed.code[0];
+        let start = body.get_operators_reader().unwrap().original_position();
+        let section_start = parsed.code_section_start;
+        (
+            (start - section_start) as u64,
+            (body.range().end - section_start) as u64,
+        )
+    }
+
+    let mut wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func (export "foo") (param i32)
+                local.get 0
+                call $ret
+                unreachable))"#,
+    )
+    .unwrap();
+    let (start, end) = code_range(&ParsedModule::read(Parser::new(0), &wasm).unwrap());
+
+    // .debug_rnglists and .debug_loclists with offset pairs and lengths
+    let encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 5,
+        address_size: 4,
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+    let unit = &mut dwarf.unit;
+    let offset_pair = unit.ranges.add(RangeList(vec![write::Range::OffsetPair {
+        begin: start,
+        end,
+    }]));
+    let start_length = unit.ranges.add(RangeList(vec![write::Range::StartLength {
+        begin: Address::Constant(start),
+        length: end - start,
+    }]));
+    let location = unit.locations.add(LocationList(vec![Location::OffsetPair {
+        begin: start,
+        end,
+        data: Expression::new(),
+    }]));
+    let root = unit.root();
+    let die = unit.get_mut(root);
+    die.set(
+        gimli::DW_AT_low_pc,
+        AttributeValue::Address(Address::Constant(0)),
+    );
+    die.set(
+        gimli::DW_AT_ranges,
+        AttributeValue::RangeListRef(offset_pair),
+    );
+    let subprogram = unit.add(root, gimli::DW_TAG_subprogram);
+    let die = unit.get_mut(subprogram);
+    die.set(
+        gimli::DW_AT_ranges,
+        AttributeValue::RangeListRef(start_length),
+    );
+    let variable = unit.add(subprogram, gimli::DW_TAG_variable);
+    let die = unit.get_mut(variable);
+    die.set(
+        gimli::DW_AT_location,
+        AttributeValue::LocationListRef(location),
+    );
+    append_dwarf(&mut wasm, &mut dwarf);
+
+    let woven = weave_with_debug_info(&wasm);
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    let dwarf = load_dwarf(&parsed);
+    let unit = dwarf.units().next().unwrap().unwrap();
+    let unit = dwarf.unit(unit).unwrap();
+    let unit = unit.unit_ref(&dwarf);
+    let mut ranges = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs().unwrap() {
+        let mut die_ranges = unit.die_ranges(entry).unwrap();
+        while let Some(range) = die_ranges.next().unwrap() {
+            ranges.push((range.begin, range.end));
+        }
+        if let Some(attr) = entry.attr_value(gimli::DW_AT_location).unwrap() {
+            let mut locations = unit.attr_locations(attr).unwrap().unwrap();
+            while let Some(location) = locations.next().unwrap() {
+                ranges.push((location.range.begin, location.range.end));
+            }
+        }
+    }
+
+    // The marker call shrinks from 2 bytes to a 1 byte return
+    let (new_start, new_end) = code_range(&parsed);
+    assert_eq!(new_end, end - 1);
+    assert_eq!(ranges, [(new_start, new_end); 3]);
+}
+
+#[cfg(test)]
+fn append_dwarf(wasm: &mut Vec<u8>, dwarf: &mut gimli::write::DwarfUnit) {
+    let mut sections = write::Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections).unwrap();
+
+    let mut custom = wasm_encoder::Module::new();
+    sections
+        .for_each(|id, data| {
+            custom.section(&CustomSection {
+                name: id.name().into(),
+                data: data.slice().into(),
+            });
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+    // Skip the header
+    wasm.extend_from_slice(&custom.finish()[8..]);
+}
+
+#[cfg(test)]
+fn weave_with_debug_info(wasm: &[u8]) -> Vec<u8> {
+    let parsed = crate::parse::ParsedModule::read(wasmparser::Parser::new(0), wasm).unwrap();
+    let mut module = wasm_encoder::Module::new();
+    crate::weaver::Weaver::new(&parsed)
+        .keep_debug_info(true)
+        .encode(&mut module)
+        .unwrap();
+    module.finish()
+}
+
+#[cfg(test)]
+fn load_dwarf<'a>(
+    parsed: &crate::parse::ParsedModule<'a>,
+) -> read::Dwarf<EndianSlice<'a, LittleEndian>> {
+    let load = |id: SectionId| -> Result<_, gimli::Error> {
+        let data = parsed
+            .custom
+            .iter()
+            .find(|section| section.name() == id.name())
+            .map(|section| section.data())
+            .unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    read::Dwarf::load(load).unwrap()
+}
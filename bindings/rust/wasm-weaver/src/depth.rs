@@ -0,0 +1,113 @@
+//! Call depth limiting for predictable recursion limits in untrusted modules.
+//!
+//! Function entries increment a global and call `__call_depth_exceeded` if it is above the
+//! limit, which traps with an integer overflow. Every exit decrements it, so bodies are
+//! wrapped in a block that branches to the function label also leave through. Exported
+//! functions are wrapped to reset the depth on every call from the host, since traps
+//! skip the exits.
+
+use wasm_encoder::{BlockType, Function, Instruction};
+
+/// Export that returns the deepest call depth reached so far.
+pub const HIGH_WATER_EXPORT: &str = "max_call_depth_reached";
+/// Name of the function that traps when the limit is exceeded.
+pub const EXCEEDED_NAME: &str = "__call_depth_exceeded";
+
+/// Indices of the items added for depth limiting.
+#[derive(Debug)]
+pub struct DepthLimit {
+    pub max: u32,
+    /// Depth of the current call, counting the exported function
+    pub depth_global: u32,
+    pub high_water_global: u32,
+    pub exceeded: u32,
+    pub get_high_water: u32,
+}
+
+impl DepthLimit {
+    /// Increments the depth, traps if it is above the limit and records the high-water mark.
+    pub fn enter(&self, func: &mut Function) {
+        func.instruction(&Instruction::GlobalGet(self.depth_global))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::I32Add)
+            .instruction(&Instruction::GlobalSet(self.depth_global))
+            .instruction(&Instruction::GlobalGet(self.depth_global))
+            .instruction(&Instruction::I32Const(self.max as i32))
+            .instruction(&Instruction::I32GtU)
+            .instruction(&Instruction::If(BlockType::Empty))
+            .instruction(&Instruction::Call(self.exceeded))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::GlobalGet(self.depth_global))
+            .instruction(&Instruction::GlobalGet(self.high_water_global))
+            .instruction(&Instruction::I32GtU)
+            .instruction(&Instruction::If(BlockType::Empty))
+            .instruction(&Instruction::GlobalGet(self.depth_global))
+            .instruction(&Instruction::GlobalSet(self.high_water_global))
+            .instruction(&Instruction::End);
+    }
+
+    pub fn leave(&self, func: &mut Function) {
+        func.instruction(&Instruction::GlobalGet(self.depth_global))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::I32Sub)
+            .instruction(&Instruction::GlobalSet(self.depth_global));
+    }
+
+    pub fn reset(&self, func: &mut Function) {
+        func.instruction(&Instruction::I32Const(0))
+            .instruction(&Instruction::GlobalSet(self.depth_global));
+    }
+
+    pub fn exceeded_body(&self) -> Function {
+        let mut func = Function::new([]);
+        func.instruction(&Instruction::I32Const(i32::MIN))
+            .instruction(&Instruction::I32Const(-1))
+            .instruction(&Instruction::I32DivS)
+            .instruction(&Instruction::Drop)
+            .instruction(&Instruction::End);
+        func
+    }
+
+    pub fn get_high_water_body(&self) -> Function {
+        let mut func = Function::new([]);
+        func.instruction(&Instruction::GlobalGet(self.high_water_global))
+            .instruction(&Instruction::End);
+        func
+    }
+}
+
+#[test]
+fn test_returns_leave() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func (export "foo") (param i32)
+                local.get 0
+                if
+                    i32.const 1
+                    call $ret
+                end
+                i32.const 2
+                call $ret
+                unreachable)
+            (func (export "bar") (param i32)
+                local.get 0
+                br_if 0
+                return_call $baz)
+            (func $baz
+                return))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        max_call_depth: Some(16),
+        exports: crate::ExportFilter::keep_all(),
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let text = crate::text::print(&woven, &Default::default()).unwrap();
+
+    // Exits at both markers, `return_call`, `return` and after every body
+    assert_eq!(text.matches("i32.add").count(), 3, "{text}");
+    assert_eq!(text.matches("i32.sub").count(), 7, "{text}");
+    assert!(text.contains(&format!("(export \"{HIGH_WATER_EXPORT}\"")));
+}
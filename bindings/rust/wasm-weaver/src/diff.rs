@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+use crate::{
+    ExportFilter,
+    inspect::{self, ExportDesc, ExportInfo, ImportInfo},
+    parse::ParsedModule,
+    weaver::WeaveError,
+};
+
+/// A difference between the exports or imports of two modules.
+#[derive(Debug)]
+pub enum Change<T> {
+    Added(T),
+    Removed(T),
+    Changed { old: T, new: T },
+}
+
+/// Compares the exports of two modules by name, in the order of the new module.
+pub fn exports<'a>(
+    old: &ParsedModule<'a>,
+    new: &ParsedModule<'a>,
+) -> Result<Vec<Change<ExportInfo<'a>>>, WeaveError> {
+    Ok(compare(
+        inspect::exports(old, &ExportFilter::default())?,
+        inspect::exports(new, &ExportFilter::default())?,
+        |export| (&export.name, "", &export.desc),
+    ))
+}
+
+/// Compares the imports of two modules by module and name, in the order of the new module.
+pub fn imports<'a>(
+    old: &ParsedModule<'a>,
+    new: &ParsedModule<'a>,
+) -> Result<Vec<Change<ImportInfo<'a>>>, WeaveError> {
+    Ok(compare(
+        inspect::imports(old)?,
+        inspect::imports(new)?,
+        |import| (import.module, import.name, &import.desc),
+    ))
+}
+
+fn compare<'a, T>(
+    mut old: Vec<T>,
+    new: Vec<T>,
+    key: impl Fn(&T) -> (&str, &str, &ExportDesc<'a>),
+) -> Vec<Change<T>> {
+    let mut changes = Vec::new();
+    for new in new {
+        let (module, name, desc) = key(&new);
+        match old.iter().position(|old| {
+            let (old_module, old_name, _) = key(old);
+            (old_module, old_name) == (module, name)
+        }) {
+            None => changes.push(Change::Added(new)),
+            Some(index) => {
+                let old = old.remove(index);
+                if key(&old).2 != desc {
+                    changes.push(Change::Changed { old, new });
+                }
+            }
+        }
+    }
+    changes.extend(old.into_iter().map(Change::Removed));
+    changes
+}
+
+impl Change<ExportInfo<'_>> {
+    /// Saved ProtoFlux graphs reference exports by name and signature, so only additions and
+    /// changes the host cannot observe are compatible.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            Change::Added(_) => false,
+            Change::Removed(_) => true,
+            Change::Changed { old, new } => match (&old.desc, &new.desc) {
+                (ExportDesc::Func(old), ExportDesc::Func(new)) => {
+                    old.params != new.params || old.results != new.results
+                }
+                (ExportDesc::Memory(old), ExportDesc::Memory(new)) => {
+                    old.memory64 != new.memory64 || old.shared != new.shared
+                }
+                (ExportDesc::Table(old), ExportDesc::Table(new)) => {
+                    old.element_type != new.element_type
+                }
+                _ => true,
+            },
+        }
+    }
+}
+
+impl Change<ImportInfo<'_>> {
+    /// New or changed imports may not be provided by hosts that could load the old module.
+    pub fn is_breaking(&self) -> bool {
+        !matches!(self, Change::Removed(_))
+    }
+}
+
+impl<T: Display> Display for Change<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(item) => write!(f, "+ {item}"),
+            Change::Removed(item) => write!(f, "- {item}"),
+            Change::Changed { old, new } => write!(f, "- {old}\n+ {new}"),
+        }
+    }
+}
+
+#[test]
+fn test_export_changes() {
+    let old = wat::parse_str(
+        r#"(module
+            (func (export "kept") (param i32))
+            (func (export "changed") (param i32))
+            (func (export "removed")))"#,
+    )
+    .unwrap();
+    let new = wat::parse_str(
+        r#"(module
+            (func (export "added"))
+            (func (export "changed") (param i64))
+            (func (export "kept") (param i32)))"#,
+    )
+    .unwrap();
+    let old = ParsedModule::read(wasmparser::Parser::new(0), &old).unwrap();
+    let new = ParsedModule::read(wasmparser::Parser::new(0), &new).unwrap();
+
+    let changes = exports(&old, &new).unwrap();
+    let names = changes
+        .iter()
+        .map(|change| match change {
+            Change::Added(export) => ('+', &*export.name),
+            Change::Removed(export) => ('-', &*export.name),
+            Change::Changed { new, .. } => ('~', &*new.name),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, [('+', "added"), ('~', "changed"), ('-', "removed")]);
+}
+
+#[test]
+fn test_breaking_changes() {
+    let old = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f32" (func (param f32) (result f32)))
+            (import "math" "cos_f32" (func (param f32) (result f32)))
+            (import "__export_returns" "foo" (func (param i32 i32)))
+            (func (export "foo") (param i32) unreachable)
+            (memory (export "memory") 1))"#,
+    )
+    .unwrap();
+    let new = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f32" (func (param f32) (result f32)))
+            (import "math" "tan_f32" (func (param f32) (result f32)))
+            (func (export "foo") (param i32) (result i32 i32) unreachable)
+            (memory (export "memory") 2))"#,
+    )
+    .unwrap();
+    let old = ParsedModule::read(wasmparser::Parser::new(0), &old).unwrap();
+    let new = ParsedModule::read(wasmparser::Parser::new(0), &new).unwrap();
+
+    // Weaving `foo` and growing the memory is not observable by graphs
+    let exports = exports(&old, &new).unwrap();
+    assert_eq!(exports.len(), 2);
+    assert!(exports.iter().all(|change| !change.is_breaking()));
+
+    let imports = imports(&old, &new).unwrap();
+    let lines = imports
+        .iter()
+        .map(|change| (change.to_string(), change.is_breaking()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            (
+                "+ func   math.tan_f32: (float -> float) ; (param f32)(result f32)".into(),
+                true
+            ),
+            (
+                "- func   math.cos_f32: (float -> float) ; (param f32)(result f32)".into(),
+                false
+            ),
+        ]
+    );
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+
+use glob::Pattern;
+use wasmparser::{Export, ExternalKind, TypeRef};
+
+use crate::{
+    parse::{ParsedModule, RETURNS_MODULE},
+    returns,
+    weaver::WeaveError,
+};
+
+/// Selects and renames the exports of the woven module.
+///
+/// By default, only memories, functions exported by `#[export_function]` and exports
+/// declared in the `frooxengine.returns` section are kept, which hides linker exports
+/// such as `__data_end`, `__heap_base` and helper functions. Modules without either,
+/// e.g. from WAT or C, keep all of their functions.
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    /// Keeps matching exports in addition to the default ones.
+    pub allow: Vec<Pattern>,
+    /// Removes matching exports, even if allowed.
+    pub deny: Vec<Pattern>,
+    /// New names by original name. Takes precedence over the prefix.
+    pub rename: HashMap<String, String>,
+    /// Prepended to the names of exported functions.
+    pub prefix: String,
+}
+
+impl ExportFilter {
+    /// Keeps every export under its original name.
+    pub fn keep_all() -> Self {
+        Self {
+            allow: vec![Pattern::new("*").unwrap()],
+            ..Self::default()
+        }
+    }
+
+    /// Returns the kept exports with their new names.
+    pub fn apply<'m, 'a>(
+        &self,
+        parsed: &'m ParsedModule<'a>,
+    ) -> Result<Vec<(&'m Export<'a>, String)>, WeaveError> {
+        let declared = returns::read(parsed)?;
+        let markers = parsed
+            .imports
+            .iter()
+            .filter(|import| import.module == RETURNS_MODULE)
+            .filter(|import| matches!(import.ty, TypeRef::Func(_)))
+            .map(|import| import.name)
+            .chain(declared.keys().map(String::as_str))
+            .collect::<HashSet<_>>();
+        // Aliases of marked functions are kept as well
+        let marked = parsed
+            .exports
+            .iter()
+            .filter(|export| export.kind == ExternalKind::Func && markers.contains(export.name))
+            .map(|export| export.index)
+            .collect::<HashSet<_>>();
+
+        let mut names = HashSet::new();
+        let mut exports = Vec::new();
+        for export in &parsed.exports {
+            let default = match export.kind {
+                ExternalKind::Memory => true,
+                ExternalKind::Func => markers.is_empty() || marked.contains(&export.index),
+                _ => false,
+            };
+            let allowed = default || self.allow.iter().any(|p| p.matches(export.name));
+            if !allowed || self.deny.iter().any(|p| p.matches(export.name)) {
+                continue;
+            }
+
+            let name = match self.rename.get(export.name) {
+                Some(name) => name.clone(),
+                None if export.kind == ExternalKind::Func => {
+                    format!("{}{}", self.prefix, export.name)
+                }
+                None => export.name.to_string(),
+            };
+            if !names.insert(name.clone()) {
+                return Err(WeaveError::DuplicateExport(name));
+            }
+            exports.push((export, name));
+        }
+        Ok(exports)
+    }
+}
+
+#[test]
+fn test_default_exports() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func (param i32)))
+            (func $foo (export "foo") (export "foo_alias") unreachable)
+            (func (export "helper"))
+            (memory (export "memory") 1)
+            (global (export "__heap_base") i32 (i32.const 0))
+            (global (export "__data_end") i32 (i32.const 0)))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+    let names = |filter: &ExportFilter| {
+        let exports = filter.apply(&parsed).unwrap();
+        exports
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(&ExportFilter::default()),
+        ["foo", "foo_alias", "memory"]
+    );
+
+    let filter = ExportFilter {
+        allow: vec![Pattern::new("__*").unwrap()],
+        deny: vec![Pattern::new("*_alias").unwrap()],
+        rename: HashMap::from([("foo".into(), "Foo".into())]),
+        prefix: "my_".into(),
+    };
+    assert_eq!(
+        names(&filter),
+        ["Foo", "memory", "__heap_base", "__data_end"]
+    );
+
+    let filter = ExportFilter {
+        rename: HashMap::from([("foo".into(), "foo_alias".into())]),
+        ..ExportFilter::default()
+    };
+    assert!(matches!(
+        filter.apply(&parsed),
+        Err(WeaveError::DuplicateExport(name)) if name == "foo_alias"
+    ));
+}
@@ -0,0 +1,179 @@
+//! Fuel metering for hosts without engine-level fuel or epoch interruption.
+//!
+//! Function entries and loop headers subtract the number of instructions up to the next
+//! loop from a global. Exported functions are wrapped to reset it to the budget on every
+//! call from the host. Running out calls `__fuel_exhausted`, which traps with an integer
+//! division by zero, unlike Rust panics which execute `unreachable`.
+
+use wasm_encoder::{BlockType, Function, Instruction};
+use wasmparser::{BinaryReaderError, FunctionBody, Operator};
+
+/// Export that sets the budget of every call, in instructions.
+pub const SET_BUDGET_EXPORT: &str = "set_fuel_budget";
+/// Name of the function that traps when the fuel is exhausted.
+pub const EXHAUSTED_NAME: &str = "__fuel_exhausted";
+
+/// Indices of the items added for metering.
+#[derive(Debug)]
+pub struct Metering {
+    /// Budget of every call, saturated to fit the signed fuel global
+    pub budget: i64,
+    /// Remaining fuel of the current call
+    pub fuel_global: u32,
+    pub budget_global: u32,
+    pub exhausted: u32,
+    pub set_budget: u32,
+}
+
+impl Metering {
+    /// Subtracts the cost and traps if the fuel is exhausted.
+    pub fn charge(&self, func: &mut Function, cost: u64) {
+        func.instruction(&Instruction::GlobalGet(self.fuel_global))
+            .instruction(&Instruction::I64Const(cost.try_into().unwrap_or(i64::MAX)))
+            .instruction(&Instruction::I64Sub)
+            .instruction(&Instruction::GlobalSet(self.fuel_global))
+            .instruction(&Instruction::GlobalGet(self.fuel_global))
+            .instruction(&Instruction::I64Const(0))
+            .instruction(&Instruction::I64LtS)
+            .instruction(&Instruction::If(BlockType::Empty))
+            .instruction(&Instruction::Call(self.exhausted))
+            .instruction(&Instruction::End);
+    }
+
+    pub fn exhausted_body(&self) -> Function {
+        let mut func = Function::new([]);
+        func.instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::I32Const(0))
+            .instruction(&Instruction::I32DivU)
+            .instruction(&Instruction::Drop)
+            .instruction(&Instruction::End);
+        func
+    }
+
+    pub fn set_budget_body(&self) -> Function {
+        let mut func = Function::new([]);
+        func.instruction(&Instruction::LocalGet(0))
+            .instruction(&Instruction::GlobalSet(self.budget_global))
+            .instruction(&Instruction::End);
+        func
+    }
+
+    pub fn reset(&self, func: &mut Function) {
+        func.instruction(&Instruction::GlobalGet(self.budget_global))
+            .instruction(&Instruction::GlobalSet(self.fuel_global));
+    }
+}
+
+/// Counts the instructions charged at the function entry and at every loop header,
+/// in the order of the loops. Instructions of nested loops are charged by these loops.
+pub fn costs(body: &FunctionBody) -> Result<Vec<u64>, BinaryReaderError> {
+    let mut costs = vec![0];
+    // The charged loop of every open block, starting with the function body
+    let mut frames = vec![0];
+    for op in body.get_operators_reader()? {
+        let op = op?;
+        let Some(&frame) = frames.last() else {
+            break;
+        };
+        costs[frame] += 1;
+        match op {
+            Operator::Loop { .. } => {
+                frames.push(costs.len());
+                costs.push(0);
+            }
+            Operator::Block { .. }
+            | Operator::If { .. }
+            | Operator::Try { .. }
+            | Operator::TryTable { .. } => frames.push(frame),
+            Operator::End | Operator::Delegate { .. } => {
+                frames.pop();
+            }
+            _ => (),
+        }
+    }
+    Ok(costs)
+}
+
+#[test]
+fn test_costs() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (func (param i32)
+                block
+                    loop
+                        local.get 0
+                        br_if 1
+                        loop
+                            br 0
+                        end
+                        br 0
+                    end
+                end
+                nop))"#,
+    )
+    .unwrap();
+    let parsed = crate::parse::ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+
+    // block, loop, end, nop, end | local.get, br_if, loop, br, end | br, end
+    assert_eq!(costs(&parsed.code[0]).unwrap(), [5, 5, 2]);
+}
+
+#[test]
+fn test_exports_are_wrapped() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func $foo (export "foo") (export "bar")
+                loop
+                    br 0
+                end
+                i32.const 1
+                call $ret
+                unreachable))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        fuel: Some(100),
+        exports: crate::ExportFilter::keep_all(),
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = crate::parse::ParsedModule::read(wasmparser::Parser::new(0), &woven).unwrap();
+
+    // $foo, __fuel_exhausted, set_fuel_budget and one wrapper for both exports
+    assert_eq!(parsed.code.len(), 4);
+    let exports = parsed
+        .exports
+        .iter()
+        .map(|export| (export.name, export.index))
+        .collect::<Vec<_>>();
+    assert_eq!(exports, [("foo", 3), ("bar", 3), (SET_BUDGET_EXPORT, 2)]);
+    assert_eq!(parsed.globals.len(), 2);
+}
+
+#[test]
+fn test_budget_saturated_and_export_taken() {
+    use crate::{Error, WeaveError};
+
+    let wasm = wat::parse_str(r#"(module (func (export "set_fuel_budget") (param i64)))"#).unwrap();
+    let options = crate::Options {
+        fuel: Some(u64::MAX),
+        exports: crate::ExportFilter::keep_all(),
+        ..Default::default()
+    };
+    let result = crate::weave_with(&wasm, &options);
+    let Err(Error::Weave(WeaveError::DuplicateExport(name))) = result else {
+        panic!("expected a duplicate export error");
+    };
+    assert_eq!(name, SET_BUDGET_EXPORT);
+
+    let wasm = wat::parse_str(r#"(module (func (export "foo")))"#).unwrap();
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = crate::parse::ParsedModule::read(wasmparser::Parser::new(0), &woven).unwrap();
+    let budget = parsed.globals[1]
+        .init_expr
+        .get_operators_reader()
+        .read()
+        .unwrap();
+    assert!(matches!(budget, Operator::I64Const { value: i64::MAX }));
+}
@@ -0,0 +1,212 @@
+//! Multi-value results for host imports.
+//!
+//! Rust can't declare imports with multiple results, so `bindgen` generates a shim that
+//! calls the import in the module `__import_results:<module>` with a trailing pointer to a
+//! `#[repr(C)]` struct of the results. The name is `<name>:<results>`, listing the fields of
+//! the struct, e.g. `decompose:f32,f32,f32`. Weaving turns it into the real import and
+//! calls it through an adapter which stores the results.
+
+use wasm_encoder::{Function, Instruction, MemArg};
+use wasmparser::{FuncType, Import, ValType};
+
+use crate::weaver::WeaveError;
+
+pub const MODULE_PREFIX: &str = "__import_results:";
+
+/// A field of the results struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    V128,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            "v128" => Self::V128,
+            _ => return None,
+        })
+    }
+
+    pub fn val_type(self) -> ValType {
+        match self {
+            Self::I8 | Self::I16 | Self::I32 => ValType::I32,
+            Self::I64 => ValType::I64,
+            Self::F32 => ValType::F32,
+            Self::F64 => ValType::F64,
+            Self::V128 => ValType::V128,
+        }
+    }
+
+    /// Size and alignment in bytes.
+    fn size(self) -> u64 {
+        match self {
+            Self::I8 => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::I64 | Self::F64 => 8,
+            Self::V128 => 16,
+        }
+    }
+
+    fn memarg(self, offset: u64) -> MemArg {
+        MemArg {
+            offset,
+            align: self.size().trailing_zeros(),
+            memory_index: 0,
+        }
+    }
+
+    fn store(self, offset: u64) -> Instruction<'static> {
+        let memarg = self.memarg(offset);
+        match self {
+            Self::I8 => Instruction::I32Store8(memarg),
+            Self::I16 => Instruction::I32Store16(memarg),
+            Self::I32 => Instruction::I32Store(memarg),
+            Self::I64 => Instruction::I64Store(memarg),
+            Self::F32 => Instruction::F32Store(memarg),
+            Self::F64 => Instruction::F64Store(memarg),
+            Self::V128 => Instruction::V128Store(memarg),
+        }
+    }
+
+    pub fn load(self, offset: u64) -> Instruction<'static> {
+        let memarg = self.memarg(offset);
+        match self {
+            Self::I8 => Instruction::I32Load8S(memarg),
+            Self::I16 => Instruction::I32Load16S(memarg),
+            Self::I32 => Instruction::I32Load(memarg),
+            Self::I64 => Instruction::I64Load(memarg),
+            Self::F32 => Instruction::F32Load(memarg),
+            Self::F64 => Instruction::F64Load(memarg),
+            Self::V128 => Instruction::V128Load(memarg),
+        }
+    }
+
+    pub fn local_type(self) -> wasm_encoder::ValType {
+        match self {
+            Self::I8 | Self::I16 | Self::I32 => wasm_encoder::ValType::I32,
+            Self::I64 => wasm_encoder::ValType::I64,
+            Self::F32 => wasm_encoder::ValType::F32,
+            Self::F64 => wasm_encoder::ValType::F64,
+            Self::V128 => wasm_encoder::ValType::V128,
+        }
+    }
+}
+
+/// Offsets of the fields in a `#[repr(C)]` struct.
+pub fn layout(fields: &[Field]) -> impl Iterator<Item = (Field, u64)> {
+    fields.iter().scan(0u64, |end, &field| {
+        let offset = end.next_multiple_of(field.size());
+        *end = offset + field.size();
+        Some((field, offset))
+    })
+}
+
+/// An import that returns through a pointer.
+#[derive(Debug)]
+pub struct ImportResults<'a> {
+    pub module: &'a str,
+    pub name: &'a str,
+    pub results: Vec<Field>,
+}
+
+impl<'a> ImportResults<'a> {
+    /// Returns `None` if the import doesn't follow the convention.
+    pub fn parse(import: &Import<'a>) -> Result<Option<Self>, WeaveError> {
+        let Some(module) = import.module.strip_prefix(MODULE_PREFIX) else {
+            return Ok(None);
+        };
+        let invalid = || WeaveError::InvalidImportResults(format!("{}.{}", module, import.name));
+        let (name, results) = import.name.rsplit_once(':').ok_or_else(invalid)?;
+        let results = results
+            .split(',')
+            .map(Field::parse)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        Ok(Some(Self {
+            module,
+            name,
+            results,
+        }))
+    }
+
+    /// Parameters of the real import, without the results pointer.
+    pub fn params<'t>(&self, marker: &'t FuncType) -> Result<&'t [ValType], WeaveError> {
+        match marker.params().split_last() {
+            Some((ValType::I32, params)) if marker.results().is_empty() => Ok(params),
+            _ => Err(WeaveError::InvalidImportResults(format!(
+                "{}.{}",
+                self.module, self.name
+            ))),
+        }
+    }
+
+    /// Calls the import with the parameters of the adapter and stores the results at the
+    /// pointer, laid out like a `#[repr(C)]` struct.
+    pub fn adapter_body(&self, params: u32, import: u32) -> Function {
+        let locals = self.results.iter().map(|field| (1, field.local_type()));
+        let mut func = Function::new(locals);
+        for local in 0..params {
+            func.instruction(&Instruction::LocalGet(local));
+        }
+        func.instruction(&Instruction::Call(import));
+        // The result locals follow the pointer parameter
+        let first_result = params + 1;
+        for i in (0..self.results.len() as u32).rev() {
+            func.instruction(&Instruction::LocalSet(first_result + i));
+        }
+        for (i, (field, offset)) in layout(&self.results).enumerate() {
+            func.instruction(&Instruction::LocalGet(params))
+                .instruction(&Instruction::LocalGet(first_result + i as u32))
+                .instruction(&field.store(offset));
+        }
+        func.instruction(&Instruction::End);
+        func
+    }
+}
+
+#[test]
+fn test_adapter() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param f32 i32)))
+            (import "__import_results:math" "decompose:f32,i8,f32"
+                (func $decompose (param f32 i32)))
+            (func (export "foo")
+                f32.const 1
+                i32.const 16
+                call $decompose
+                i32.const 16
+                f32.load
+                i32.const 16
+                i32.load8_u offset=4
+                call $ret
+                unreachable)
+            (memory (export "memory") 1))"#,
+    )
+    .unwrap();
+    let woven = crate::weave(&wasm).unwrap();
+    let text = crate::text::print(&woven, &Default::default()).unwrap();
+
+    assert!(
+        text.contains(r#"(import "math" "decompose" (func (;0;) (type 0)))"#),
+        "{text}"
+    );
+    assert!(text.contains("(type (;0;) (func (param f32) (result f32 i32 f32)))"));
+    // The `i8` is padded like in the struct
+    assert!(text.contains("i32.store8 offset=4"));
+    assert!(text.contains("f32.store offset=8"));
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use wasmparser::*;
+
+use crate::{
+    ExportFilter,
+    parse::{FunctionLookup, FunctionLookupEntry, ParsedModule, RETURNS_MODULE, TypeLookup},
+    returns,
+    weaver::{WeaveError, propagate_results, read_calls},
+};
+
+/// An export of a module as it would appear after weaving.
+#[derive(Debug)]
+pub struct ExportInfo<'a> {
+    /// The name after renaming.
+    pub name: String,
+    pub desc: ExportDesc<'a>,
+}
+
+/// An import of a module, except for the marker imports removed by weaving.
+#[derive(Debug)]
+pub struct ImportInfo<'a> {
+    pub module: &'a str,
+    pub name: &'a str,
+    /// Imported functions are never marked or re-exported.
+    pub desc: ExportDesc<'a>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExportDesc<'a> {
+    Func(FunctionSignature<'a>),
+    Global(GlobalType),
+    Memory(MemoryType),
+    Table(TableType),
+    Tag(TagType),
+}
+
+/// The signature of an exported function after weaving.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature<'a> {
+    pub params: Box<[ValType]>,
+    pub results: Box<[ValType]>,
+    /// Where the results are declared if the module is not woven yet, a marker import
+    /// or the returns section.
+    pub results_from: Option<&'static str>,
+    /// The export re-exports this imported function (module, name).
+    pub reexport: Option<(&'a str, &'a str)>,
+}
+
+/// Collects the exports kept by the filter with their post-weave names and signatures.
+pub fn exports<'a>(
+    parsed: &ParsedModule<'a>,
+    filter: &ExportFilter,
+) -> Result<Vec<ExportInfo<'a>>, WeaveError> {
+    let ty_lookup = TypeLookup::new(&parsed.types);
+    let fn_lookup = FunctionLookup::new(parsed);
+
+    let mut markers = HashMap::new();
+    // Results of the markers and of the functions that call them, directly or not
+    let mut marker_results = HashMap::new();
+    let mut fn_import_index = 0;
+    for import in &parsed.imports {
+        let TypeRef::Func(ty) = import.ty else {
+            if import.module == RETURNS_MODULE {
+                return Err(WeaveError::ReturnsMarkerIsNotFunction(
+                    import.name.to_string(),
+                ));
+            }
+            continue;
+        };
+        if import.module == RETURNS_MODULE {
+            let params = ty_lookup.try_get(ty)?.try_fn_ty()?.params();
+            markers.insert(import.name, params);
+            marker_results.insert(fn_import_index, params);
+        }
+        fn_import_index += 1;
+    }
+    let calls = read_calls(parsed, &mut BTreeSet::new())?;
+    propagate_results(&calls, fn_lookup.index_of_body(0), &mut marker_results)
+        .map_err(|conflict| conflict.into_error())?;
+    let declared = returns::read(parsed)?;
+
+    let mut exports = Vec::with_capacity(parsed.exports.len());
+    for (export, name) in filter.apply(parsed)? {
+        let out_of_bounds = || WeaveError::ExportIndexOutOfBounds(export.name.to_string());
+        let desc = match export.kind {
+            ExternalKind::Func => {
+                let func = fn_lookup.try_get(export.index)?;
+                let func_ty = ty_lookup.try_get(func.ty())?.try_fn_ty()?;
+                // The body may never reach the marker, e.g. if it always traps
+                let marker = (marker_results.get(&export.index)).or(markers.get(export.name));
+                let (results, results_from) = if let Some(marker) = marker {
+                    ((*marker).into(), Some(RETURNS_MODULE))
+                } else if let Some(returns) = declared.get(export.name) {
+                    (returns.val_types().into(), Some(returns::SECTION))
+                } else {
+                    (func_ty.results().into(), None)
+                };
+                ExportDesc::Func(FunctionSignature {
+                    params: func_ty.params().into(),
+                    results,
+                    results_from,
+                    reexport: match func {
+                        FunctionLookupEntry::Import { import, .. } => {
+                            Some((import.module, import.name))
+                        }
+                        FunctionLookupEntry::Body { .. } => None,
+                    },
+                })
+            }
+            ExternalKind::Global => {
+                ExportDesc::Global(parsed.global_type(export.index).ok_or_else(out_of_bounds)?)
+            }
+            ExternalKind::Memory => {
+                ExportDesc::Memory(parsed.memory_type(export.index).ok_or_else(out_of_bounds)?)
+            }
+            ExternalKind::Table => {
+                ExportDesc::Table(parsed.table_type(export.index).ok_or_else(out_of_bounds)?)
+            }
+            ExternalKind::Tag => {
+                ExportDesc::Tag(parsed.tag_type(export.index).ok_or_else(out_of_bounds)?)
+            }
+        };
+        exports.push(ExportInfo { name, desc });
+    }
+
+    Ok(exports)
+}
+
+/// Collects all imports of a module that remain after weaving.
+pub fn imports<'a>(parsed: &ParsedModule<'a>) -> Result<Vec<ImportInfo<'a>>, WeaveError> {
+    let ty_lookup = TypeLookup::new(&parsed.types);
+
+    let mut imports = Vec::with_capacity(parsed.imports.len());
+    for import in &parsed.imports {
+        if import.module == RETURNS_MODULE {
+            continue;
+        }
+        let desc = match import.ty {
+            TypeRef::Func(ty) => {
+                let func_ty = ty_lookup.try_get(ty)?.try_fn_ty()?;
+                ExportDesc::Func(FunctionSignature {
+                    params: func_ty.params().into(),
+                    results: func_ty.results().into(),
+                    results_from: None,
+                    reexport: None,
+                })
+            }
+            TypeRef::Global(ty) => ExportDesc::Global(ty),
+            TypeRef::Memory(ty) => ExportDesc::Memory(ty),
+            TypeRef::Table(ty) => ExportDesc::Table(ty),
+            TypeRef::Tag(ty) => ExportDesc::Tag(ty),
+        };
+        imports.push(ImportInfo {
+            module: import.module,
+            name: import.name,
+            desc,
+        });
+    }
+
+    Ok(imports)
+}
+
+/// Maps a value type to the C# type used by the host (`FunctionSignature.MapTypes`).
+pub fn csharp_type(ty: ValType) -> Option<&'static str> {
+    Some(match ty {
+        ValType::I32 => "int",
+        ValType::I64 => "long",
+        ValType::F32 => "float",
+        ValType::F64 => "double",
+        ValType::V128 => "V128",
+        ValType::Ref(ty) => match ty.heap_type() {
+            HeapType::Abstract {
+                shared: false,
+                ty: AbstractHeapType::Func | AbstractHeapType::Extern | AbstractHeapType::Any,
+            } => "object",
+            _ => return None,
+        },
+    })
+}
+
+impl Display for ExportInfo<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_desc(f, &self.name, &self.desc)
+    }
+}
+
+impl Display for ImportInfo<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_desc(
+            f,
+            &format_args!("{}.{}", self.module, self.name),
+            &self.desc,
+        )
+    }
+}
+
+fn write_desc(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &dyn Display,
+    desc: &ExportDesc,
+) -> std::fmt::Result {
+    match desc {
+        ExportDesc::Func(sig) => write!(f, "func   {name}: {sig}"),
+        ExportDesc::Global(ty) => {
+            write!(f, "global {name}: ")?;
+            write_csharp_types(f, &[ty.content_type])?;
+            write!(f, " ; {}", ty.content_type)?;
+            if ty.mutable {
+                f.write_str(", mutable")?;
+            }
+            Ok(())
+        }
+        ExportDesc::Memory(ty) => {
+            write!(f, "memory {name}: {}", ty.initial)?;
+            if let Some(max) = ty.maximum {
+                write!(f, "..{max}")?;
+            }
+            f.write_str(" pages")?;
+            if ty.memory64 {
+                f.write_str(", 64-bit")?;
+            }
+            if ty.shared {
+                f.write_str(", shared")?;
+            }
+            Ok(())
+        }
+        ExportDesc::Table(ty) => {
+            write!(f, "table  {name}: {} x {}", ty.element_type, ty.initial)?;
+            if let Some(max) = ty.maximum {
+                write!(f, "..{max}")?;
+            }
+            Ok(())
+        }
+        ExportDesc::Tag(ty) => write!(f, "tag    {name}: type {}", ty.func_type_idx),
+    }
+}
+
+/// Formats like the host's `FunctionSignature.ToString`, followed by the WebAssembly types.
+impl Display for FunctionSignature<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(")?;
+        write_csharp_types(f, &self.params)?;
+        f.write_str(" -> ")?;
+        write_csharp_types(f, &self.results)?;
+        f.write_str(") ; ")?;
+        write_wasm_types(f, "param", &self.params)?;
+        write_wasm_types(f, "result", &self.results)?;
+        if let Some(source) = self.results_from {
+            write!(f, ", results from {source}")?;
+        }
+        if let Some((module, name)) = self.reexport {
+            write!(f, ", re-exports {module}.{name}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_csharp_types(f: &mut std::fmt::Formatter<'_>, types: &[ValType]) -> std::fmt::Result {
+    if types.is_empty() {
+        return f.write_str("void");
+    }
+    for (i, ty) in types.iter().enumerate() {
+        if i != 0 {
+            f.write_str(" ")?;
+        }
+        match csharp_type(*ty) {
+            Some(name) => f.write_str(name)?,
+            None => write!(f, "<unsupported {ty}>")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_wasm_types(
+    f: &mut std::fmt::Formatter<'_>,
+    kind: &str,
+    types: &[ValType],
+) -> std::fmt::Result {
+    if types.is_empty() {
+        return Ok(());
+    }
+    write!(f, "({kind}")?;
+    for ty in types {
+        write!(f, " {ty}")?;
+    }
+    f.write_str(")")
+}
+
+#[test]
+fn test_exports_unwoven() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32 i64)))
+            (func (export "foo") (param f32)
+                i32.const 1
+                i64.const 2
+                call $ret
+                unreachable)
+            (memory (export "memory") 1)
+            (global (export "__heap_base") i32 (i32.const 0)))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &wasm).unwrap();
+    let exports = exports(&parsed, &ExportFilter::default()).unwrap();
+
+    let lines = exports.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "func   foo: (float -> int long) ; (param f32)(result i32 i64), results from __export_returns",
+            "memory memory: 1 pages",
+        ]
+    );
+}
+
+#[test]
+fn test_exports_outlined_and_aliased() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "a" (func $ret (param i32 i32)))
+            (func $impl (param i32)
+                local.get 0
+                local.get 0
+                call $ret
+                unreachable)
+            (func $a (export "a") (export "b") (param i32)
+                local.get 0
+                call $impl))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &wasm).unwrap();
+    let exports = exports(&parsed, &ExportFilter::default()).unwrap();
+
+    assert_eq!(exports.len(), 2);
+    for export in &exports {
+        let ExportDesc::Func(signature) = &export.desc else {
+            panic!("{} is a function", export.name);
+        };
+        assert_eq!(&*signature.results, [ValType::I32, ValType::I32]);
+    }
+}
@@ -0,0 +1,216 @@
+//! Post-processes WebAssembly modules for the Resonite WebAssembly plugin.
+//!
+//! Functions exported with `#[export_function]` pass their results to a
+//! marker import in the `__export_returns` namespace. Weaving removes the
+//! markers and turns these functions into multi-value functions.
+
+use wasm_encoder::{Module, reencode};
+use wasmparser::{BinaryReaderError, Parser};
+
+use crate::{parse::ParsedModule, polyfill::Polyfill, snapshot::Snapshot, weaver::Weaver};
+
+mod dce;
+pub mod debug_info;
+pub mod depth;
+pub mod diff;
+pub mod exports;
+pub mod fuel;
+pub mod import_results;
+pub mod inspect;
+pub mod lint;
+pub mod manifest;
+pub mod nan;
+pub mod nodes;
+pub mod parse;
+pub mod polyfill;
+pub mod returns;
+pub mod snapshot;
+pub mod text;
+mod type_allocator;
+pub mod weaver;
+
+pub use crate::{
+    dce::DceStats, exports::ExportFilter, lint::Lint, manifest::Manifest, weaver::WeaveError,
+};
+
+/// Options for [`weave_with`].
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Removes all custom sections, including names and producers.
+    pub strip: bool,
+    /// Keeps DWARF debug info and remaps its code addresses.
+    /// Otherwise, it is removed since weaving invalidates it.
+    pub debug_info: bool,
+    /// Removes functions, globals, imports and types that are unreachable.
+    pub dce: bool,
+    /// Exports to keep, by default the memory and `#[export_function]` functions,
+    /// or all functions of modules without markers.
+    pub exports: ExportFilter,
+    /// Meters guest code with this per-call budget, in instructions.
+    pub fuel: Option<u64>,
+    /// Traps if calls nest deeper than this, counting the exported function.
+    pub max_call_depth: Option<u32>,
+    /// Replaces NaNs produced by arithmetic, reinterpreted or stored with the canonical NaN.
+    pub canonicalize_nans: bool,
+    /// Adds a `frooxengine.nodes` section describing the exported functions.
+    pub embed_nodes: bool,
+    /// Host modules whose imports are replaced with pure WebAssembly functions.
+    pub polyfills: Vec<Polyfill>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed to parse module: {0}")]
+    Parse(#[from] BinaryReaderError),
+    #[error(transparent)]
+    Weave(WeaveError),
+    #[error("failed to re-encode module: {0}")]
+    Reencode(String),
+    #[error("woven module is invalid: {0}")]
+    Invalid(BinaryReaderError),
+    #[error("failed to parse text: {0}")]
+    Text(#[from] wat::Error),
+    #[error("failed to print module: {0}")]
+    Print(String),
+    #[error(transparent)]
+    Snapshot(#[from] snapshot::SnapshotError),
+}
+
+impl From<WeaveError> for Error {
+    fn from(value: WeaveError) -> Self {
+        match value {
+            WeaveError::Parse(error) => Self::Parse(error),
+            error => Self::Weave(error),
+        }
+    }
+}
+
+impl From<reencode::Error<WeaveError>> for Error {
+    fn from(value: reencode::Error<WeaveError>) -> Self {
+        match value {
+            reencode::Error::UserError(error) => error.into(),
+            reencode::Error::ParseError(error) => Self::Parse(error),
+            error => Self::Reencode(error.to_string()),
+        }
+    }
+}
+
+/// Weaves a module with the default options.
+pub fn weave(wasm: &[u8]) -> Result<Vec<u8>, Error> {
+    weave_with(wasm, &Options::default())
+}
+
+/// Weaves a module and validates the result.
+pub fn weave_with(wasm: &[u8], options: &Options) -> Result<Vec<u8>, Error> {
+    weave_with_stats(wasm, options).map(|(module, _)| module)
+}
+
+/// Like [`weave_with`], but also returns what dead-code elimination removed, if enabled.
+pub fn weave_with_stats(
+    wasm: &[u8],
+    options: &Options,
+) -> Result<(Vec<u8>, Option<DceStats>), Error> {
+    let parsed = ParsedModule::read(Parser::new(0), wasm)?;
+
+    let mut module = Module::new();
+    let stats = Weaver::new(&parsed)
+        .strip(options.strip)
+        .embed_nodes(options.embed_nodes)
+        .keep_debug_info(options.debug_info)
+        .exports(options.exports.clone())
+        .eliminate_dead_code(options.dce)
+        .meter_fuel(options.fuel)
+        .limit_call_depth(options.max_call_depth)
+        .canonicalize_nans(options.canonicalize_nans)
+        .polyfill(options.polyfills.clone())
+        .encode(&mut module)?;
+    let module = module.finish();
+
+    wasmparser::validate(&module).map_err(Error::Invalid)?;
+
+    Ok((module, stats))
+}
+
+/// Runs the `init` export of a woven module at build time and bakes the resulting memory
+/// and globals into it. The init export, the start function and the code only they reach
+/// are removed.
+pub fn snapshot(wasm: &[u8], init: &str) -> Result<Vec<u8>, Error> {
+    let parsed = ParsedModule::read(Parser::new(0), wasm)?;
+    let snapshot = Snapshot::take(&parsed, wasm, init)?;
+
+    let exports = ExportFilter {
+        deny: vec![glob::Pattern::new(&glob::Pattern::escape(init)).expect("escaped pattern")],
+        ..ExportFilter::keep_all()
+    };
+    let mut module = Module::new();
+    Weaver::new(&parsed)
+        .exports(exports)
+        .eliminate_dead_code(true)
+        .restore_snapshot(Some(snapshot))
+        .encode(&mut module)?;
+    let module = module.finish();
+
+    wasmparser::validate(&module).map_err(Error::Invalid)?;
+
+    Ok(module)
+}
+
+/// Checks a woven module against the limits of the host and, if given, the imports it defines.
+pub fn check(wasm: &[u8], manifest: Option<&Manifest>) -> Result<Vec<Lint>, Error> {
+    let parsed = ParsedModule::read(Parser::new(0), wasm)?;
+    let mut lints = lint::lint(&parsed)?;
+    if let Some(manifest) = manifest {
+        lints.extend(manifest.check(&parsed)?);
+    }
+    Ok(lints)
+}
+
+/// Checks that the host defines every import of a woven module with the same type.
+pub fn check_imports(wasm: &[u8], manifest: &Manifest) -> Result<Vec<Lint>, Error> {
+    let parsed = ParsedModule::read(Parser::new(0), wasm)?;
+    Ok(manifest.check(&parsed)?)
+}
+
+#[test]
+fn test_weave_start_and_data_count() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func $start
+                i32.const 0
+                i32.const 0
+                i32.const 1
+                memory.init $data)
+            (func (export "foo")
+                i32.const 1
+                call $ret
+                unreachable)
+            (memory 1)
+            (start $start)
+            (data $data "\01"))"#,
+    )
+    .unwrap();
+
+    let woven = weave(&wasm).unwrap();
+
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    assert_eq!(parsed.start, Some(0));
+    assert_eq!(parsed.data_count, Some(1));
+}
+
+#[test]
+fn test_weave_unmarked_exports() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1)))
+            (memory (export "memory") 1))"#,
+    )
+    .unwrap();
+
+    let woven = weave(&wasm).unwrap();
+
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    let names = parsed.exports.iter().map(|export| export.name);
+    assert_eq!(names.collect::<Vec<_>>(), ["add", "memory"]);
+}
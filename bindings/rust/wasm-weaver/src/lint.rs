@@ -0,0 +1,124 @@
+use wasmparser::*;
+
+use crate::{
+    ExportFilter,
+    inspect::{self, ExportDesc},
+    parse::ParsedModule,
+    weaver::WeaveError,
+};
+
+/// Maximum number of parameters, limited by the `ActionTypes`/`FuncTypes` tables of the host.
+pub const MAX_PARAMS: usize = 16;
+/// Maximum number of results, limited by the `TupleTypes` table of the host.
+pub const MAX_RESULTS: usize = 8;
+
+/// String imports which access the `memory` export of the instance.
+const MEMORY_IMPORTS: &[(&str, &str)] = &[("string", "read_wtf16"), ("string", "new_wtf16")];
+
+/// A violation of a limit imposed by the Resonite host.
+#[derive(thiserror::Error, Debug)]
+pub enum Lint {
+    #[error("export {export:?} has {count} parameters, the host supports at most {MAX_PARAMS}")]
+    TooManyParams { export: String, count: usize },
+    #[error("export {export:?} has {count} results, the host supports at most {MAX_RESULTS}")]
+    TooManyResults { export: String, count: usize },
+    #[error("export {export:?} uses value type {ty}, which the host does not support")]
+    UnsupportedType { export: String, ty: ValType },
+    #[error("import {module}.{name} accesses linear memory, but there is no \"memory\" export")]
+    MissingMemory { module: String, name: String },
+    #[error("import {module}.{name} is not defined by the host")]
+    UnknownImport { module: String, name: String },
+    #[error("import {module}.{name} has type {found}, but the host defines {expected}")]
+    ImportMismatch {
+        module: String,
+        name: String,
+        expected: String,
+        found: String,
+    },
+}
+
+/// Checks the exported signatures of a woven module against the host limits.
+pub fn lint(parsed: &ParsedModule) -> Result<Vec<Lint>, WeaveError> {
+    let mut lints = Vec::new();
+    let mut has_memory = false;
+
+    for export in inspect::exports(parsed, &ExportFilter::keep_all())? {
+        let sig = match export.desc {
+            ExportDesc::Func(sig) => sig,
+            ExportDesc::Memory(_) if export.name == "memory" => {
+                has_memory = true;
+                continue;
+            }
+            _ => continue,
+        };
+        if sig.params.len() > MAX_PARAMS {
+            lints.push(Lint::TooManyParams {
+                export: export.name.to_string(),
+                count: sig.params.len(),
+            });
+        }
+        if sig.results.len() > MAX_RESULTS {
+            lints.push(Lint::TooManyResults {
+                export: export.name.to_string(),
+                count: sig.results.len(),
+            });
+        }
+        for ty in sig.params.iter().chain(&sig.results) {
+            if !is_supported(*ty) {
+                lints.push(Lint::UnsupportedType {
+                    export: export.name.to_string(),
+                    ty: *ty,
+                });
+            }
+        }
+    }
+
+    if !has_memory {
+        for import in &parsed.imports {
+            if MEMORY_IMPORTS.contains(&(import.module, import.name)) {
+                lints.push(Lint::MissingMemory {
+                    module: import.module.to_string(),
+                    name: import.name.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(lints)
+}
+
+/// The host maps `funcref` to `object`, but can't do anything with it.
+fn is_supported(ty: ValType) -> bool {
+    match ty {
+        ValType::Ref(ty) if ty.is_func_ref() => false,
+        ty => inspect::csharp_type(ty).is_some(),
+    }
+}
+
+#[test]
+fn test_lint() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "string" "new_wtf16" (func (param i32 i32) (result externref)))
+            (func (export "params")
+                (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32))
+            (func (export "results") (result i32 i32 i32 i32 i32 i32 i32 i32 i32)
+                unreachable)
+            (func (export "funcref") (param funcref))
+            (func (export "fine") (param externref) (result i32 i32)
+                unreachable))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &wasm).unwrap();
+    let lints = lint(&parsed).unwrap();
+
+    assert!(matches!(
+        lints.as_slice(),
+        [
+            Lint::TooManyParams { count: 17, .. },
+            Lint::TooManyResults { count: 9, .. },
+            Lint::UnsupportedType { .. },
+            Lint::MissingMemory { .. },
+        ]
+    ));
+}
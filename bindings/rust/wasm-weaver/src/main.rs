@@ -1,55 +1,308 @@
-use std::{error::Error, io::Read, path::PathBuf};
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 use clap::Parser as _;
-use wasm_encoder::Module;
+use wasm_weaver::{ExportFilter, Manifest, diff::Change, inspect, parse::ParsedModule};
 use wasmparser::Parser;
 
-use crate::{args::BuildArgs, parse::ParsedModule, weaver::Weaver};
+use crate::{
+    args::{
+        BuildArgs, DiffArgs, Emit, LintLevel, MessageFormat, SnapshotArgs, WatArgs, WeaveOptions,
+    },
+    report::{Reporter, WeaveSummary},
+};
 
 mod args;
 mod cargo;
-mod parse;
-mod type_allocator;
-mod weaver;
+mod report;
+mod watch;
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> ExitCode {
     let command = args::RootArgs::parse();
 
-    match command {
-        args::RootArgs::Build(args) => build(args),
-        args::RootArgs::Weave(args::WeaveArgs { input, output }) => weave(input, output),
+    let result = match command {
+        args::RootArgs::Build(args) => build(&args),
+        args::RootArgs::Watch(args) => watch::watch(args),
+        args::RootArgs::Weave(args::WeaveArgs {
+            input,
+            output,
+            options,
+        }) => {
+            let report = Reporter {
+                format: MessageFormat::Human,
+            };
+            let name = input.display().to_string();
+            weave(&input, &output, &options, report, &name).map(|summary| {
+                // Only the savings are worth reporting for a single file
+                if summary.dce.is_some() {
+                    report.weave_finished(&name, &output, Ok(&summary));
+                }
+            })
+        }
+        args::RootArgs::Wat(args) => wat(args),
+        args::RootArgs::Snapshot(args) => snapshot(args),
+        args::RootArgs::Diff(args) => diff(args),
+        args::RootArgs::Inspect(args::InspectArgs { input }) => inspect(input),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn build(args: BuildArgs) -> Result<(), Box<dyn Error>> {
-    match cargo::build(&args)? {
-        None => {
-            eprintln!("No artifacts");
-            std::process::exit(1);
+fn build(args: &BuildArgs) -> Result<(), Box<dyn Error>> {
+    let report = Reporter {
+        format: args.message_format,
+    };
+    let build = cargo::build(args)?;
+
+    let targets = match (&args.out_dir, &args.output) {
+        (Some(out_dir), _) => {
+            std::fs::create_dir_all(out_dir)
+                .map_err(|e| format!("failed to create {}: {e}", out_dir.display()))?;
+            build
+                .artifacts
+                .iter()
+                .map(|artifact| {
+                    let name = format!("{}.{}", artifact.name, args.options.emit.extension());
+                    (artifact, out_dir.join(name))
+                })
+                .collect()
         }
-        Some(artifact) => weave(artifact, args.output),
+        (None, output) => match build.artifacts.as_slice() {
+            [] if !build.success => return Err("cargo build failed".into()),
+            [] => return Err("cargo did not produce a WebAssembly artifact".into()),
+            [artifact] => vec![(artifact, output.clone().unwrap_or_default())],
+            artifacts => {
+                return Err(format!(
+                    "cargo produced {} WebAssembly artifacts, use --out-dir to weave all of them",
+                    artifacts.len()
+                )
+                .into());
+            }
+        },
+    };
+
+    let mut failed = 0;
+    for (artifact, output) in &targets {
+        report.weave_started(&artifact.name, &artifact.path);
+        let result = weave(
+            &artifact.path,
+            output,
+            &args.options,
+            report,
+            &artifact.name,
+        );
+        report.weave_finished(&artifact.name, output, result.as_ref().map_err(|e| &**e));
+        failed += result.is_err() as usize;
+    }
+
+    if !build.success {
+        return Err("cargo build failed".into());
+    }
+    if failed > 0 {
+        return Err(format!("failed to weave {failed} of {} artifacts", targets.len()).into());
+    }
+    Ok(())
+}
+
+fn weave(
+    input: &Path,
+    output: &Path,
+    options: &WeaveOptions,
+    report: Reporter,
+    name: &str,
+) -> Result<WeaveSummary, Box<dyn Error>> {
+    let wasm_buf = read(input)?;
+    let wasm_buf = wasm_weaver::text::parse(&wasm_buf)?;
+
+    let rename = match &options.rename_exports {
+        Some(path) => serde_json::from_slice(&read(path)?)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?,
+        None => Default::default(),
+    };
+    let weave_options = wasm_weaver::Options {
+        strip: options.strip,
+        debug_info: options.debug_info,
+        dce: options.dce,
+        fuel: options.fuel,
+        max_call_depth: options.max_call_depth,
+        canonicalize_nans: options.canonicalize_nans,
+        embed_nodes: options.embed_nodes,
+        polyfills: options
+            .polyfill
+            .iter()
+            .map(|&module| module.into())
+            .collect(),
+        exports: ExportFilter {
+            allow: options.allow_export.clone(),
+            deny: options.deny_export.clone(),
+            rename,
+            prefix: options.export_prefix.clone(),
+        },
+    };
+    let (module_buf, dce) = wasm_weaver::weave_with_stats(&wasm_buf, &weave_options)?;
+    if let Some(path) = &options.nodes {
+        let parsed = ParsedModule::read(Parser::new(0), &wasm_buf)?;
+        let nodes = wasm_weaver::nodes::describe(&parsed, &weave_options.exports)?;
+        write_atomic(path, &serde_json::to_vec_pretty(&nodes)?)
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+    }
+    if options.lint != LintLevel::Allow {
+        let lints = wasm_weaver::check(&module_buf, None)?;
+        let level = match options.lint {
+            LintLevel::Deny => "error",
+            _ => "warning",
+        };
+        for lint in &lints {
+            report.lint(name, level, lint);
+        }
+        if options.lint == LintLevel::Deny && !lints.is_empty() {
+            return Err("module does not satisfy the requirements of the host".into());
+        }
+    }
+    // The host can't instantiate a module with an import it doesn't define
+    if let Some(manifest) = &options.manifest {
+        let lints = wasm_weaver::check_imports(&module_buf, &Manifest::load(manifest)?)?;
+        for lint in &lints {
+            report.lint(name, "error", lint);
+        }
+        if !lints.is_empty() {
+            return Err("module imports items the host does not define".into());
+        }
+    }
+
+    let text;
+    let contents = match options.emit {
+        Emit::Wasm => &module_buf,
+        Emit::Wat => {
+            text = wasm_weaver::text::print(&module_buf, &options.print.options())?;
+            text.as_bytes()
+        }
+    };
+    write_atomic(output, contents)
+        .map_err(|e| format!("failed to write {}: {e}", output.display()))?;
+
+    Ok(WeaveSummary {
+        input_size: wasm_buf.len(),
+        output_size: contents.len(),
+        dce,
+        exports: ParsedModule::read(Parser::new(0), &module_buf)?
+            .exports
+            .len(),
+    })
+}
+
+fn wat(args: WatArgs) -> Result<(), Box<dyn Error>> {
+    let wasm_buf = read(&args.input)?;
+    let wasm_buf = wasm_weaver::text::parse(&wasm_buf)?;
+    let module_buf = wasm_weaver::weave(&wasm_buf)?;
+    let text = wasm_weaver::text::print(&module_buf, &args.print.options())?;
+
+    match args.output {
+        Some(output) => write_atomic(&output, text.as_bytes())
+            .map_err(|e| format!("failed to write {}: {e}", output.display()))?,
+        None => print!("{text}"),
     }
+    Ok(())
 }
 
-fn weave(input: PathBuf, output: PathBuf) -> Result<(), Box<dyn Error>> {
-    let mut file = std::fs::OpenOptions::new().read(true).open(input)?;
-    let file_size: usize = file.metadata()?.len().try_into()?;
-    let mut wasm_buf = Vec::with_capacity(file_size);
-    file.read_to_end(&mut wasm_buf)?;
-    drop(file);
-    // Since this program is only invoked once per binary, it is fine to leak
-    let wasm_buf: &'static [u8] = wasm_buf.leak();
+fn snapshot(args: SnapshotArgs) -> Result<(), Box<dyn Error>> {
+    let wasm_buf = read(&args.input)?;
+    let wasm_buf = wasm_weaver::text::parse(&wasm_buf)?;
+    let module_buf = wasm_weaver::snapshot(&wasm_buf, &args.init)?;
 
-    let parsed = ParsedModule::read(Parser::new(0), wasm_buf)?;
+    write_atomic(&args.output, &module_buf)
+        .map_err(|e| format!("failed to write {}: {e}", args.output.display()))?;
+    println!(
+        "{}: {} -> {} bytes",
+        args.output.display(),
+        wasm_buf.len(),
+        module_buf.len()
+    );
+    Ok(())
+}
 
-    let weaver = Weaver::new(&parsed);
-    let mut module = Module::new();
-    weaver.encode(&mut module)?;
-    let module_buf = module.finish();
+fn diff(args: DiffArgs) -> Result<(), Box<dyn Error>> {
+    let old_buf = read(&args.old)?;
+    let old_buf = wasm_weaver::text::parse(&old_buf)?;
+    let new_buf = read(&args.new)?;
+    let new_buf = wasm_weaver::text::parse(&new_buf)?;
+    let old = ParsedModule::read(Parser::new(0), &old_buf)?;
+    let new = ParsedModule::read(Parser::new(0), &new_buf)?;
 
-    wasmparser::validate(&module_buf)?;
+    let exports = wasm_weaver::diff::exports(&old, &new)?;
+    let imports = wasm_weaver::diff::imports(&old, &new)?;
+    if exports.is_empty() && imports.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
 
-    std::fs::write(output, &module_buf)?;
+    let breaking = print_changes("Exports", &exports, |c| c.is_breaking())
+        + print_changes("Imports", &imports, |c| c.is_breaking());
+    if breaking > 0 {
+        return Err(format!("found {}", report::count(breaking, "breaking change")).into());
+    }
+    Ok(())
+}
+
+/// Prints the changes with their classification and returns the number of breaking changes.
+fn print_changes<T: Display>(
+    heading: &str,
+    changes: &[Change<T>],
+    is_breaking: fn(&Change<T>) -> bool,
+) -> usize {
+    if changes.is_empty() {
+        return 0;
+    }
+    println!("{heading}:");
+    let mut breaking = 0;
+    for change in changes {
+        let label = match is_breaking(change) {
+            true => "breaking",
+            false => "compatible",
+        };
+        for (i, line) in change.to_string().lines().enumerate() {
+            println!("  {:10} {line}", if i == 0 { label } else { "" });
+        }
+        breaking += is_breaking(change) as usize;
+    }
+    breaking
+}
+
+fn inspect(input: PathBuf) -> Result<(), Box<dyn Error>> {
+    let wasm_buf = read(&input)?;
+    let wasm_buf = wasm_weaver::text::parse(&wasm_buf)?;
+    wasmparser::validate(&wasm_buf).map_err(|e| format!("invalid module: {e}"))?;
+
+    let parsed = ParsedModule::read(Parser::new(0), &wasm_buf)?;
+
+    for export in inspect::exports(&parsed, &ExportFilter::default())? {
+        println!("{export}");
+    }
 
     Ok(())
 }
+
+fn read(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()).into())
+}
+
+/// Writes through a temporary file, so the output is never read while incomplete.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path)
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashMap, path::Path};
+
+use bindgen::imports::{ImportItem, Namespaces, WasmType};
+use wasmparser::*;
+
+use crate::{
+    lint::Lint,
+    parse::{ParsedModule, RETURNS_MODULE, TypeLookup},
+    weaver::WeaveError,
+};
+
+/// The imports defined by the host linker, read from `LibFrooxEngine.json`.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    items: HashMap<(String, String), HostItem>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("{0}.{1} uses the $f template type, but its name does not contain $f")]
+pub struct UninstantiatedTemplate(String, String);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManifestError {
+    #[error("failed to read manifest: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse manifest: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Template(#[from] UninstantiatedTemplate),
+}
+
+#[derive(Debug)]
+enum HostItem {
+    Function(FuncType),
+    Global(ValType),
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let reader = std::fs::OpenOptions::new().read(true).open(path)?;
+        let namespaces: Namespaces = serde_json::from_reader(reader)?;
+        Ok(Self::new(&namespaces)?)
+    }
+
+    /// Expands `$f` templates the same way `bindgen` does.
+    pub fn new(namespaces: &Namespaces) -> Result<Self, UninstantiatedTemplate> {
+        let mut manifest = Self::default();
+        for (ns, items) in namespaces {
+            for (name, item) in items {
+                if name.contains("$f") {
+                    for instance in [WasmType::F32, WasmType::F64] {
+                        let name = name.replace("$f", instance.typ().unwrap());
+                        manifest.insert(ns, name, item, Some(&instance))?;
+                    }
+                    continue;
+                }
+                manifest.insert(ns, name.clone(), item, None)?;
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn insert(
+        &mut self,
+        ns: &str,
+        name: String,
+        item: &ImportItem,
+        instance: Option<&WasmType>,
+    ) -> Result<(), UninstantiatedTemplate> {
+        let lower = |ty: &WasmType| {
+            let ty = match instance {
+                Some(instance) => ty.make_instance(instance),
+                None => ty,
+            };
+            lower(ty).ok_or_else(|| UninstantiatedTemplate(ns.to_string(), name.clone()))
+        };
+        let item = match item {
+            ImportItem::Function {
+                parameters,
+                results,
+                ..
+            } => HostItem::Function(FuncType::new(
+                parameters
+                    .iter()
+                    .map(lower)
+                    .collect::<Result<Vec<_>, _>>()?,
+                results.iter().map(lower).collect::<Result<Vec<_>, _>>()?,
+            )),
+            ImportItem::Global { value_type, .. } => HostItem::Global(lower(value_type)?),
+        };
+        self.items.insert((ns.to_string(), name), item);
+        Ok(())
+    }
+
+    /// Checks that every import of the module is defined by the host with a matching type.
+    pub fn check(&self, parsed: &ParsedModule) -> Result<Vec<Lint>, WeaveError> {
+        let ty_lookup = TypeLookup::new(&parsed.types);
+        let mut lints = Vec::new();
+
+        for import in &parsed.imports {
+            if import.module == RETURNS_MODULE {
+                continue;
+            }
+            let key = (import.module.to_string(), import.name.to_string());
+            let (expected, found) = match (self.items.get(&key), import.ty) {
+                (None, _) => {
+                    lints.push(Lint::UnknownImport {
+                        module: key.0,
+                        name: key.1,
+                    });
+                    continue;
+                }
+                (Some(HostItem::Function(expected)), TypeRef::Func(ty)) => {
+                    let found = ty_lookup.try_get(ty)?.try_fn_ty()?;
+                    if expected == found {
+                        continue;
+                    }
+                    (expected.to_string(), found.to_string())
+                }
+                (Some(HostItem::Global(expected)), TypeRef::Global(found)) => {
+                    if *expected == found.content_type {
+                        continue;
+                    }
+                    (expected.to_string(), found.content_type.to_string())
+                }
+                (Some(HostItem::Function(expected)), found) => {
+                    (expected.to_string(), describe(found).to_string())
+                }
+                (Some(HostItem::Global(expected)), found) => {
+                    (format!("global {expected}"), describe(found).to_string())
+                }
+            };
+            lints.push(Lint::ImportMismatch {
+                module: key.0,
+                name: key.1,
+                expected,
+                found,
+            });
+        }
+
+        Ok(lints)
+    }
+}
+
+/// Lowers a manifest type to the WebAssembly type of the `wasm32` C ABI.
+fn lower(ty: &WasmType) -> Option<ValType> {
+    Some(match ty {
+        WasmType::I8 | WasmType::I16 | WasmType::I32 => ValType::I32,
+        WasmType::Pointer | WasmType::PointerMut => ValType::I32,
+        WasmType::I64 => ValType::I64,
+        WasmType::F32 => ValType::F32,
+        WasmType::F64 => ValType::F64,
+        WasmType::V128 => ValType::V128,
+        WasmType::Externref => ValType::EXTERNREF,
+        WasmType::TemplateFloat => return None,
+    })
+}
+
+fn describe(ty: TypeRef) -> &'static str {
+    match ty {
+        TypeRef::Func(_) => "function",
+        TypeRef::Table(_) => "table",
+        TypeRef::Memory(_) => "memory",
+        TypeRef::Global(_) => "global",
+        TypeRef::Tag(_) => "tag",
+    }
+}
+
+#[test]
+fn test_check() {
+    let namespaces = serde_json::from_str(
+        r#"{
+            "math": {
+                "sin_$f": { "type": "function", "parameters": ["$f"], "results": ["$f"] }
+            },
+            "string": {
+                "len_wtf16": { "type": "function", "parameters": ["externref"], "results": ["i32"] }
+            }
+        }"#,
+    )
+    .unwrap();
+    let manifest = Manifest::new(&namespaces).unwrap();
+
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f32" (func (param f32) (result f32)))
+            (import "math" "sin_f64" (func (param f64) (result f32)))
+            (import "math" "cos_f32" (func (param f32) (result f32)))
+            (import "string" "len_wtf16" (global i32))
+            (import "__export_returns" "foo" (func (param i32))))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &wasm).unwrap();
+    let lints = manifest.check(&parsed).unwrap();
+
+    assert!(matches!(
+        lints.as_slice(),
+        [
+            Lint::ImportMismatch { name: a, .. },
+            Lint::UnknownImport { name: b, .. },
+            Lint::ImportMismatch { name: c, .. },
+        ] if a == "sin_f64" && b == "cos_f32" && c == "len_wtf16"
+    ));
+}
@@ -0,0 +1,188 @@
+//! NaN canonicalization for bit-identical results across CPUs.
+//!
+//! The NaN bit patterns produced by float arithmetic depend on the hardware, which can desync
+//! clients once they are observed through reinterpret casts or memory. Results of arithmetic
+//! are replaced by the canonical NaN, as are floats before they are reinterpreted or stored,
+//! which covers NaNs passed in by the host.
+
+use wasm_encoder::{Function, Ieee32, Ieee64, Instruction, ValType};
+use wasmparser::{BinaryReaderError, FunctionBody, Operator};
+
+const CANONICAL_F32: u32 = 0x7fc0_0000;
+const CANONICAL_F64: u64 = 0x7ff8_0000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Float {
+    F32,
+    F64,
+    F32x4,
+    F64x2,
+}
+
+/// Where an instruction needs a canonical NaN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Site {
+    /// The operand on top of the stack
+    Operand(Float),
+    Result(Float),
+}
+
+pub fn site(op: &Operator) -> Option<Site> {
+    use Float::*;
+    use Operator::*;
+    Some(match op {
+        I32ReinterpretF32 | F32Store { .. } => Site::Operand(F32),
+        I64ReinterpretF64 | F64Store { .. } => Site::Operand(F64),
+        F32Add | F32Sub | F32Mul | F32Div | F32Sqrt | F32Min | F32Max | F32Ceil | F32Floor
+        | F32Trunc | F32Nearest | F32DemoteF64 => Site::Result(F32),
+        F64Add | F64Sub | F64Mul | F64Div | F64Sqrt | F64Min | F64Max | F64Ceil | F64Floor
+        | F64Trunc | F64Nearest | F64PromoteF32 => Site::Result(F64),
+        F32x4Add | F32x4Sub | F32x4Mul | F32x4Div | F32x4Sqrt | F32x4Min | F32x4Max | F32x4Ceil
+        | F32x4Floor | F32x4Trunc | F32x4Nearest | F32x4DemoteF64x2Zero | F32x4RelaxedMadd
+        | F32x4RelaxedNmadd | F32x4RelaxedMin | F32x4RelaxedMax => Site::Result(F32x4),
+        F64x2Add | F64x2Sub | F64x2Mul | F64x2Div | F64x2Sqrt | F64x2Min | F64x2Max | F64x2Ceil
+        | F64x2Floor | F64x2Trunc | F64x2Nearest | F64x2PromoteLowF32x4 | F64x2RelaxedMadd
+        | F64x2RelaxedNmadd | F64x2RelaxedMin | F64x2RelaxedMax => Site::Result(F64x2),
+        _ => return None,
+    })
+}
+
+/// Locals that hold a float while it is checked for NaN.
+#[derive(Debug, Default)]
+pub struct Scratch {
+    f32: Option<u32>,
+    f64: Option<u32>,
+    v128: Option<u32>,
+}
+
+impl Scratch {
+    /// Allocates the locals needed by a function body, starting at `first_local`.
+    pub fn for_body(
+        body: &FunctionBody,
+        first_local: u32,
+    ) -> Result<(Self, Vec<ValType>), BinaryReaderError> {
+        let (mut f32, mut f64, mut v128) = (false, false, false);
+        for op in body.get_operators_reader()? {
+            match site(&op?) {
+                Some(Site::Operand(float) | Site::Result(float)) => match float {
+                    Float::F32 => f32 = true,
+                    Float::F64 => f64 = true,
+                    Float::F32x4 | Float::F64x2 => v128 = true,
+                },
+                None => (),
+            }
+        }
+
+        let mut scratch = Self::default();
+        let mut locals = Vec::new();
+        for (used, local, ty) in [
+            (f32, &mut scratch.f32, ValType::F32),
+            (f64, &mut scratch.f64, ValType::F64),
+            (v128, &mut scratch.v128, ValType::V128),
+        ] {
+            if used {
+                *local = Some(first_local + locals.len() as u32);
+                locals.push(ty);
+            }
+        }
+        Ok((scratch, locals))
+    }
+
+    /// Replaces the float on top of the stack by the canonical NaN if it is a NaN.
+    pub fn canonicalize(&self, func: &mut Function, float: Float) {
+        let (local, canonical, ne, select) = match float {
+            Float::F32 => (
+                self.f32,
+                Instruction::F32Const(Ieee32::new(CANONICAL_F32)),
+                Instruction::F32Ne,
+                Instruction::Select,
+            ),
+            Float::F64 => (
+                self.f64,
+                Instruction::F64Const(Ieee64::new(CANONICAL_F64)),
+                Instruction::F64Ne,
+                Instruction::Select,
+            ),
+            Float::F32x4 => (
+                self.v128,
+                Instruction::V128Const(i128::from_le_bytes(splat(&CANONICAL_F32.to_le_bytes()))),
+                Instruction::F32x4Ne,
+                Instruction::V128Bitselect,
+            ),
+            Float::F64x2 => (
+                self.v128,
+                Instruction::V128Const(i128::from_le_bytes(splat(&CANONICAL_F64.to_le_bytes()))),
+                Instruction::F64x2Ne,
+                Instruction::V128Bitselect,
+            ),
+        };
+        let local = local.expect("scratch local is allocated for every site");
+        // NaN is the only value that is not equal to itself
+        func.instruction(&Instruction::LocalSet(local))
+            .instruction(&canonical)
+            .instruction(&Instruction::LocalGet(local))
+            .instruction(&Instruction::LocalGet(local))
+            .instruction(&Instruction::LocalGet(local))
+            .instruction(&ne)
+            .instruction(&select);
+    }
+}
+
+fn splat(lane: &[u8]) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for chunk in bytes.chunks_mut(lane.len()) {
+        chunk.copy_from_slice(lane);
+    }
+    bytes
+}
+
+#[test]
+fn test_scratch_locals() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (memory 1)
+            (func (param f32 f64) (local i32)
+                i32.const 0
+                local.get 1
+                f64.store
+                local.get 0
+                i32.reinterpret_f32
+                drop))"#,
+    )
+    .unwrap();
+    let parsed = crate::parse::ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+
+    let (scratch, locals) = Scratch::for_body(&parsed.code[0], 3).unwrap();
+    assert_eq!(locals, [ValType::F32, ValType::F64]);
+    assert_eq!(
+        (scratch.f32, scratch.f64, scratch.v128),
+        (Some(3), Some(4), None)
+    );
+}
+
+#[test]
+fn test_canonicalized_sites() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32)))
+            (func (export "foo") (param f32)
+                local.get 0
+                local.get 0
+                f32.mul
+                f32.abs
+                i32.reinterpret_f32
+                call $ret
+                unreachable))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        canonicalize_nans: true,
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let text = crate::text::print(&woven, &Default::default()).unwrap();
+
+    // After the multiplication and before the reinterpret cast, not after `f32.abs`
+    assert_eq!(text.matches("f32.const nan").count(), 2, "{text}");
+    assert!(text.contains("(local f32)"));
+}
@@ -0,0 +1,198 @@
+//! Descriptions of the exported functions, for hosts and codegen steps building ProtoFlux nodes.
+//!
+//! `#[export_function]` records the parameter names and its `category` and `description`
+//! arguments as JSON objects in the `frooxengine.node_info` section, which the linker
+//! concatenates. Other languages can add the same section. Parameters without a name there
+//! are named by the `name` section.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use wasmparser::{KnownCustom, Name};
+
+use crate::{
+    ExportFilter,
+    inspect::{self, ExportDesc},
+    parse::ParsedModule,
+    weaver::WeaveError,
+};
+
+/// Section written by `#[export_function]`, removed by weaving.
+pub const INFO_SECTION: &str = "frooxengine.node_info";
+/// Section with the [`Node`]s, if embedded.
+pub const SECTION: &str = "frooxengine.nodes";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeInfo {
+    export: String,
+    #[serde(default)]
+    params: Vec<String>,
+    category: Option<String>,
+    description: Option<String>,
+}
+
+/// An exported function with its signature after weaving.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub export: String,
+    pub params: Vec<Param>,
+    /// WebAssembly types of the results.
+    pub results: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Param {
+    pub name: Option<String>,
+    /// WebAssembly type.
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// Describes the function exports kept by the filter, under their new names. Results are those
+/// of the marker the function reaches, also through outlined wrappers or under other names.
+pub fn describe(parsed: &ParsedModule, filter: &ExportFilter) -> Result<Vec<Node>, WeaveError> {
+    let mut info = read_info(parsed)?;
+    let local_names = local_names(parsed)?;
+    let signatures = inspect::exports(parsed, &ExportFilter::keep_all())?
+        .into_iter()
+        .filter_map(|export| match export.desc {
+            ExportDesc::Func(signature) => Some((export.name, signature)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut nodes = Vec::new();
+    for (export, name) in filter.apply(parsed)? {
+        let Some(signature) = signatures.get(export.name) else {
+            continue;
+        };
+        let info = info.remove(export.name);
+        let names = local_names.get(&export.index);
+        let params = (signature.params.iter().enumerate())
+            .map(|(i, ty)| Param {
+                name: (info.as_ref())
+                    .and_then(|info| info.params.get(i).cloned())
+                    .or_else(|| names.and_then(|names| names.get(&(i as u32)).cloned())),
+                ty: ty.to_string(),
+            })
+            .collect();
+        let (category, description) = info
+            .map(|info| (info.category, info.description))
+            .unwrap_or_default();
+        nodes.push(Node {
+            export: name,
+            params,
+            results: signature.results.iter().map(ToString::to_string).collect(),
+            category,
+            description,
+        });
+    }
+    Ok(nodes)
+}
+
+/// Reads the node info of all sections by export name.
+fn read_info(parsed: &ParsedModule) -> Result<HashMap<String, NodeInfo>, WeaveError> {
+    let mut info = HashMap::new();
+    for custom in &parsed.custom {
+        if custom.name() != INFO_SECTION {
+            continue;
+        }
+        for node in serde_json::Deserializer::from_slice(custom.data()).into_iter::<NodeInfo>() {
+            let node = node.map_err(|error| WeaveError::InvalidNodeInfo(error.to_string()))?;
+            info.insert(node.export.clone(), node);
+        }
+    }
+    Ok(info)
+}
+
+/// Local names of the `name` section by function and local index.
+fn local_names(parsed: &ParsedModule) -> Result<HashMap<u32, HashMap<u32, String>>, WeaveError> {
+    let mut names = HashMap::new();
+    for custom in &parsed.custom {
+        let KnownCustom::Name(reader) = custom.as_known() else {
+            continue;
+        };
+        for subsection in reader {
+            let Name::Local(functions) = subsection? else {
+                continue;
+            };
+            for function in functions {
+                let function = function?;
+                let locals = function
+                    .names
+                    .into_iter()
+                    .map(|naming| naming.map(|naming| (naming.index, naming.name.to_string())))
+                    .collect::<Result<_, _>>()?;
+                names.insert(function.index, locals);
+            }
+        }
+    }
+    Ok(names)
+}
+
+#[test]
+fn test_describe() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "split" (func (param f32 f32)))
+            (func (export "split") (param $v i64) (param $scale f32) unreachable)
+            (func $helper (export "helper") (param $n i32) (result i32) local.get 0)
+            (memory (export "memory") 1)
+            (@custom "frooxengine.node_info"
+                "{\"export\": \"split\", \"params\": [\"vector\"], \"category\": \"Math\"}"))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+    let filter = ExportFilter {
+        prefix: "my_".into(),
+        ..ExportFilter::default()
+    };
+
+    let nodes = describe(&parsed, &filter).unwrap();
+
+    let param = |name: &str, ty: &str| Param {
+        name: Some(name.into()),
+        ty: ty.into(),
+    };
+    assert_eq!(
+        nodes,
+        [Node {
+            export: "my_split".into(),
+            params: vec![param("vector", "i64"), param("scale", "f32")],
+            results: vec!["f32".into(), "f32".into()],
+            category: Some("Math".into()),
+            description: None,
+        }]
+    );
+}
+
+#[test]
+fn test_describe_outlined_and_aliased() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "a" (func $ret (param i32 i32)))
+            (func $impl (param i32)
+                local.get 0
+                local.get 0
+                call $ret
+                unreachable)
+            (func $a (export "a") (export "b") (param i32)
+                local.get 0
+                call $impl))"#,
+    )
+    .unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+
+    let nodes = describe(&parsed, &ExportFilter::default()).unwrap();
+
+    let exports = nodes.iter().map(|node| &*node.export).collect::<Vec<_>>();
+    assert_eq!(exports, ["a", "b"]);
+    for node in nodes {
+        assert_eq!(node.results, ["i32", "i32"]);
+    }
+}
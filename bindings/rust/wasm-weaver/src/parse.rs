@@ -1,9 +1,13 @@
-use std::iter::repeat;
+use std::{collections::HashMap, iter::repeat};
 
 use wasmparser::*;
 
 use crate::weaver::WeaveError;
 
+/// Import namespace of the marker functions generated by `#[export_function]`.
+/// The parameters of a marker are the results of the export with the same name.
+pub const RETURNS_MODULE: &str = "__export_returns";
+
 #[derive(Default)]
 pub struct ParsedModule<'a> {
     pub types: Box<[RecGroup]>,
@@ -19,17 +23,25 @@ pub struct ParsedModule<'a> {
     pub data_count: Option<u32>,
     pub data: Box<[Data<'a>]>,
     pub code: Box<[FunctionBody<'a>]>,
+    /// Offset of the code section contents, which DWARF addresses are relative to.
+    pub code_section_start: usize,
+    /// Offset of the first code section entry, after the function count.
+    pub code_entries_start: usize,
+    /// Custom sections in the order they appear in the module.
+    pub custom: Box<[CustomSectionReader<'a>]>,
 }
 
 impl<'a> ParsedModule<'a> {
-    pub fn read(parser: Parser, data: &'a [u8]) -> Result<Self, BinaryReaderError> {
+    pub fn read(parser: Parser, data: &'a [u8]) -> Result<Self, WeaveError> {
         let mut module = Self::default();
 
         let mut functions = Vec::new();
+        let mut custom = Vec::new();
 
         for payload in parser.parse_all(data) {
             use Payload::*;
-            match payload? {
+            let payload = payload?;
+            match payload {
                 Version { .. } => continue,
                 TypeSection(section) => {
                     module.types = collect_section(section)?;
@@ -67,7 +79,11 @@ impl<'a> ParsedModule<'a> {
                 DataSection(section) => {
                     module.data = collect_section(section)?;
                 }
-                CodeSectionStart { count, .. } => {
+                CodeSectionStart { count, range, .. } => {
+                    module.code_section_start = range.start;
+                    let mut reader = BinaryReader::new(&data[range.clone()], range.start);
+                    reader.read_var_u32()?;
+                    module.code_entries_start = reader.original_position();
                     functions.reserve_exact(count.try_into().unwrap());
                 }
                 CodeSectionEntry(function_body) => {
@@ -79,33 +95,107 @@ impl<'a> ParsedModule<'a> {
                         module.code = std::mem::take(&mut functions).into_boxed_slice();
                     }
                 }
-                ModuleSection {
-                    parser: _,
-                    unchecked_range: _,
-                } => unimplemented!("Nested Module"),
+                ModuleSection { .. } => return Err(unsupported("nested module", &payload)),
                 // Components
-                InstanceSection(_section_limited) => unimplemented!("Component Model"),
-                CoreTypeSection(_section_limited) => unimplemented!("Component Model"),
-                ComponentSection {
-                    parser: _,
-                    unchecked_range: _,
-                } => unimplemented!("Component Model"),
-                ComponentInstanceSection(_section) => unimplemented!("Component Model"),
-                ComponentAliasSection(_section) => unimplemented!("Component Model"),
-                ComponentTypeSection(_section) => unimplemented!("Component Model"),
-                ComponentCanonicalSection(_section) => unimplemented!("Component Model"),
-                ComponentStartSection { .. } => unimplemented!("Component Model"),
-                ComponentImportSection(_section) => unimplemented!("Component Model"),
-                ComponentExportSection(_section) => unimplemented!("Component Model"),
-                CustomSection(_section) => continue,
-                UnknownSection { .. } => unimplemented!("Unknown Section"),
+                InstanceSection(_)
+                | CoreTypeSection(_)
+                | ComponentSection { .. }
+                | ComponentInstanceSection(_)
+                | ComponentAliasSection(_)
+                | ComponentTypeSection(_)
+                | ComponentCanonicalSection(_)
+                | ComponentStartSection { .. }
+                | ComponentImportSection(_)
+                | ComponentExportSection(_) => {
+                    return Err(unsupported("component model section", &payload));
+                }
+                CustomSection(section) => custom.push(section),
+                UnknownSection { id, range, .. } => {
+                    return Err(WeaveError::UnknownSection {
+                        id,
+                        offset: range.start,
+                    });
+                }
                 End(_) => break,
-                pl => unimplemented!("{pl:?}"),
+                _ => return Err(unsupported("section", &payload)),
             }
         }
 
+        module.custom = custom.into_boxed_slice();
+
         Ok(module)
     }
+
+    /// Offset of the size of a function body, which may be encoded with padding.
+    pub fn code_entry_start(&self, body: u32) -> usize {
+        match body.checked_sub(1) {
+            Some(previous) => self.code[previous as usize].range().end,
+            None => self.code_entries_start,
+        }
+    }
+
+    /// Gets the type of a global in the global index space, including imports.
+    pub fn global_type(&self, index: u32) -> Option<GlobalType> {
+        let imported = self.imports.iter().filter_map(|import| match import.ty {
+            TypeRef::Global(ty) => Some(ty),
+            _ => None,
+        });
+        nth(imported.chain(self.globals.iter().map(|g| g.ty)), index)
+    }
+
+    /// Gets the type of a memory in the memory index space, including imports.
+    pub fn memory_type(&self, index: u32) -> Option<MemoryType> {
+        let imported = self.imports.iter().filter_map(|import| match import.ty {
+            TypeRef::Memory(ty) => Some(ty),
+            _ => None,
+        });
+        nth(imported.chain(self.memories.iter().copied()), index)
+    }
+
+    /// Gets the type of a table in the table index space, including imports.
+    pub fn table_type(&self, index: u32) -> Option<TableType> {
+        let imported = self.imports.iter().filter_map(|import| match import.ty {
+            TypeRef::Table(ty) => Some(ty),
+            _ => None,
+        });
+        nth(imported.chain(self.tables.iter().map(|t| t.ty)), index)
+    }
+
+    /// Gets the type of a tag in the tag index space, including imports.
+    pub fn tag_type(&self, index: u32) -> Option<TagType> {
+        let imported = self.imports.iter().filter_map(|import| match import.ty {
+            TypeRef::Tag(ty) => Some(ty),
+            _ => None,
+        });
+        nth(imported.chain(self.tags.iter().copied()), index)
+    }
+
+    /// Collects the function names of the `name` custom section.
+    pub fn function_names(&self) -> HashMap<u32, &'a str> {
+        let mut names = HashMap::new();
+        for custom in &self.custom {
+            let KnownCustom::Name(reader) = custom.as_known() else {
+                continue;
+            };
+            for subsection in reader.into_iter().flatten() {
+                if let Name::Function(map) = subsection {
+                    names.extend(map.into_iter().flatten().map(|n| (n.index, n.name)));
+                }
+            }
+        }
+        names
+    }
+}
+
+fn unsupported(kind: &'static str, payload: &Payload) -> WeaveError {
+    WeaveError::UnsupportedSection {
+        kind,
+        offset: payload.as_section().map_or(0, |(_, range)| range.start),
+    }
+}
+
+fn nth<T>(mut iter: impl Iterator<Item = T>, index: u32) -> Option<T> {
+    iter.nth(index.try_into().ok()?)
 }
 
 fn collect_section<'a, T>(section: SectionLimited<'a, T>) -> Result<Box<[T]>, BinaryReaderError>
@@ -176,7 +266,7 @@ impl<'a> TypeLookup<'a> {
 
     pub fn try_get(&self, index: u32) -> Result<TypeLookupEntry<'a>, WeaveError> {
         self.get(index)
-            .ok_or_else(|| WeaveError::TypeIndexOutOfBounds(index))
+            .ok_or(WeaveError::TypeIndexOutOfBounds(index))
     }
 }
 
@@ -251,11 +341,8 @@ impl<'m, 'a> FunctionLookup<'m, 'a> {
         for export in &module.exports {
             if export.kind == ExternalKind::Func {
                 let index: usize = export.index.try_into().unwrap();
-                match table.get_mut(index) {
-                    Some(FunctionLookupEntry::Body { export_name, .. }) => {
-                        *export_name = Some(export.name);
-                    }
-                    _ => (),
+                if let Some(FunctionLookupEntry::Body { export_name, .. }) = table.get_mut(index) {
+                    *export_name = Some(export.name);
                 }
             }
         }
@@ -278,6 +365,36 @@ impl<'m, 'a> FunctionLookup<'m, 'a> {
 
     pub fn try_get(&self, index: u32) -> Result<FunctionLookupEntry<'m, 'a>, WeaveError> {
         self.get(index)
-            .ok_or_else(|| WeaveError::FunctionIndexOutOfBounds(index))
+            .ok_or(WeaveError::FunctionIndexOutOfBounds(index))
     }
 }
+
+#[test]
+fn test_unknown_section() {
+    let mut wasm = wat::parse_str("(module)").unwrap();
+    wasm.extend([0x7f, 0x01, 0x00]);
+
+    let Err(WeaveError::UnknownSection { id, offset }) = ParsedModule::read(Parser::new(0), &wasm)
+    else {
+        panic!("expected an unknown section error");
+    };
+    assert_eq!((id, offset), (0x7f, 10));
+}
+
+#[test]
+fn test_padded_code_entry_start() {
+    #[rustfmt::skip]
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        // Padded function count and body sizes
+        0x0a, 0x0d, 0x82, 0x80, 0x80, 0x80, 0x00,
+        0x82, 0x80, 0x00, 0x00, 0x0b,
+        0x02, 0x00, 0x0b,
+    ];
+    let parsed = ParsedModule::read(Parser::new(0), &wasm).unwrap();
+    assert_eq!(parsed.code_section_start, 21);
+    assert_eq!(parsed.code_entry_start(0), 26);
+    assert_eq!(parsed.code_entry_start(1), 31);
+}
@@ -0,0 +1,372 @@
+//! Pure WebAssembly implementations of host modules, for running woven modules in a browser
+//! or any host without the Resonite linker.
+//!
+//! Imports of a polyfilled module are replaced by the functions of a bundled module with the
+//! same names, which are added to the woven module along with the functions they call and
+//! the globals of the bundled module. Bundled modules may import the memory of the woven
+//! module, but no functions or globals. The added functions are metered, depth limited and
+//! canonicalize NaNs like the functions of the module.
+//! `string` can't be polyfilled, since it creates host objects.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::LazyLock,
+};
+
+use wasm_encoder::{GlobalSection, reencode::Reencode};
+use wasmparser::{ExternalKind, FuncType, FunctionBody, Operator, Parser, TypeRef};
+
+use crate::{
+    parse::{ParsedModule, TypeLookup},
+    weaver::WeaveError,
+};
+
+type Result<T, E = wasm_encoder::reencode::Error<WeaveError>> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Polyfill {
+    /// Trigonometric, hyperbolic, exponential and logarithmic functions.
+    Math,
+    /// `wasi_snapshot_preview1` for crates using `std` on `wasm32-wasip1`. Output is discarded,
+    /// `random_get` is a deterministic PRNG, clocks return 0 and `proc_exit` traps.
+    Wasi,
+}
+
+static MATH: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    wat::parse_str(include_str!("polyfill/math.wat")).expect("the math polyfill is valid")
+});
+
+static WASI: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    wat::parse_str(include_str!("polyfill/wasi.wat")).expect("the wasi polyfill is valid")
+});
+
+impl Polyfill {
+    /// Import module the polyfill replaces.
+    pub fn module(self) -> &'static str {
+        match self {
+            Self::Math => "math",
+            Self::Wasi => "wasi_snapshot_preview1",
+        }
+    }
+
+    fn wasm(self) -> &'static [u8] {
+        match self {
+            Self::Math => &MATH,
+            Self::Wasi => &WASI,
+        }
+    }
+}
+
+/// The functions of a polyfill which replace some of its imports.
+pub struct Linked {
+    polyfill: Polyfill,
+    parsed: ParsedModule<'static>,
+    /// Functions to add in order, including the ones called by the replacements
+    pub functions: Vec<u32>,
+    /// Indices of the added functions in the woven module
+    pub function_map: HashMap<u32, u32>,
+    /// Indices of the types of the added functions in the woven module
+    pub type_map: HashMap<u32, u32>,
+    /// Indices of the added globals in the woven module
+    pub global_map: HashMap<u32, u32>,
+}
+
+impl Linked {
+    pub fn new<'n>(
+        polyfill: Polyfill,
+        names: impl IntoIterator<Item = &'n str>,
+    ) -> Result<Self, WeaveError> {
+        let parsed = ParsedModule::read(Parser::new(0), polyfill.wasm())?;
+        let linked = Self {
+            polyfill,
+            parsed,
+            functions: Vec::new(),
+            function_map: HashMap::new(),
+            type_map: HashMap::new(),
+            global_map: HashMap::new(),
+        };
+        let mut queue = Vec::new();
+        for name in names {
+            let function = linked
+                .export(name)
+                .ok_or_else(|| WeaveError::UnknownPolyfill {
+                    module: polyfill.module(),
+                    name: name.to_string(),
+                })?;
+            queue.push(function);
+        }
+
+        let mut functions = BTreeSet::new();
+        while let Some(function) = queue.pop() {
+            if !functions.insert(function) {
+                continue;
+            }
+            let body = &linked.parsed.code[function as usize];
+            for op in body.get_operators_reader()? {
+                match op? {
+                    Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                        queue.push(function_index);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(Self {
+            functions: functions.into_iter().collect(),
+            ..linked
+        })
+    }
+
+    /// Finds the function replacing an import.
+    pub fn export(&self, name: &str) -> Option<u32> {
+        self.parsed
+            .exports
+            .iter()
+            .find(|export| export.kind == ExternalKind::Func && export.name == name)
+            .map(|export| export.index)
+    }
+
+    /// Names of the added functions by new index, `<module>.<name>` with the name from the
+    /// `name` section or the export of the bundled module.
+    pub fn function_names(&self) -> Vec<(u32, String)> {
+        let mut names = self.parsed.function_names();
+        for export in &self.parsed.exports {
+            if export.kind == ExternalKind::Func {
+                names.entry(export.index).or_insert(export.name);
+            }
+        }
+        (self.functions.iter())
+            .filter_map(|function| {
+                let name = names.get(function)?;
+                let module = self.polyfill.module();
+                Some((self.function_map[function], format!("{module}.{name}")))
+            })
+            .collect()
+    }
+
+    /// Whether the functions access the memory of the woven module.
+    pub fn uses_memory(&self) -> bool {
+        (self.parsed.imports.iter()).any(|import| matches!(import.ty, TypeRef::Memory(_)))
+    }
+
+    /// Number of globals to add, in the order of [`Self::encode_globals`].
+    pub fn global_count(&self) -> u32 {
+        self.parsed.globals.len() as u32
+    }
+
+    /// Type index and type of a function.
+    pub fn func_type(&self, function: u32) -> Result<(u32, &FuncType), WeaveError> {
+        let ty = self.parsed.functions[function as usize];
+        let func_ty = TypeLookup::new(&self.parsed.types)
+            .try_get(ty)?
+            .try_fn_ty()?;
+        Ok((ty, func_ty))
+    }
+
+    pub fn body(&self, function: u32) -> &FunctionBody<'static> {
+        &self.parsed.code[function as usize]
+    }
+
+    /// Encodes the globals with their initializers.
+    pub fn encode_globals(&self, globals: &mut GlobalSection) -> Result<()> {
+        for global in &self.parsed.globals {
+            self.relocate().parse_global(globals, global.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Re-encodes instructions and types with the new indices.
+    pub fn relocate(&self) -> impl Reencode<Error = WeaveError> + '_ {
+        Relocate {
+            functions: &self.function_map,
+            types: &self.type_map,
+            globals: &self.global_map,
+        }
+    }
+}
+
+struct Relocate<'a> {
+    functions: &'a HashMap<u32, u32>,
+    types: &'a HashMap<u32, u32>,
+    globals: &'a HashMap<u32, u32>,
+}
+
+impl Reencode for Relocate<'_> {
+    type Error = WeaveError;
+
+    fn function_index(&mut self, func: u32) -> Result<u32> {
+        let index = self.functions.get(&func).copied();
+        index.ok_or(WeaveError::FunctionIndexOutOfBounds(func).into())
+    }
+
+    fn type_index(&mut self, ty: u32) -> Result<u32> {
+        let index = self.types.get(&ty).copied();
+        index.ok_or(WeaveError::TypeIndexOutOfBounds(ty).into())
+    }
+
+    fn global_index(&mut self, global: u32) -> Result<u32> {
+        let index = self.globals.get(&global).copied();
+        index.ok_or(WeaveError::GlobalIndexOutOfBounds(global).into())
+    }
+}
+
+#[test]
+fn test_math() {
+    use wasmi::{Engine, Linker, Module, Store};
+
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "pow_f64" (func $pow (param f64 f64) (result f64)))
+            (import "string" "len_wtf16" (func (param externref) (result i32)))
+            (import "math" "sin_f32" (func $sin (param f32) (result f32)))
+            (import "math" "sin_f64" (func $sin64 (param f64) (result f64)))
+            (func (export "pow") (param f64 f64) (result f64)
+                (call $pow (local.get 0) (local.get 1)))
+            (func (export "sin") (param f32) (result f32)
+                (call $sin (local.get 0)))
+            (func (export "sin64") (param f64) (result f64)
+                (call $sin64 (local.get 0))))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        exports: crate::ExportFilter::keep_all(),
+        polyfills: vec![Polyfill::Math],
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    assert!(parsed.imports.iter().all(|import| import.module != "math"));
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &woven[..]).unwrap();
+    let mut store = Store::new(&engine, ());
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("string", "len_wtf16", |_: wasmi::ExternRef| 0)
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .unwrap();
+    let pow = instance
+        .get_typed_func::<(f64, f64), f64>(&store, "pow")
+        .unwrap();
+    let sin = instance.get_typed_func::<f32, f32>(&store, "sin").unwrap();
+    let sin64 = instance
+        .get_typed_func::<f64, f64>(&store, "sin64")
+        .unwrap();
+
+    assert_eq!(pow.call(&mut store, (2.0, 10.0)).unwrap(), 1024.0);
+    assert_eq!(pow.call(&mut store, (-2.0, 3.0)).unwrap(), -8.0);
+    assert!(pow.call(&mut store, (-2.0, 0.5)).unwrap().is_nan());
+    let x = pow.call(&mut store, (1.5, -2.25)).unwrap();
+    assert!((x - 1.5f64.powf(-2.25)).abs() <= f64::EPSILON * x);
+    assert_eq!(sin.call(&mut store, 0.5).unwrap(), 0.5f32.sin());
+    for x in [1e10, 1e20, -1e20] {
+        let y = sin64.call(&mut store, x).unwrap();
+        assert!((-1.0..=1.0).contains(&y));
+        assert!((y - x.sin()).abs() <= f64::EPSILON, "sin({x}) = {y}");
+    }
+}
+
+#[test]
+fn test_instrumented() {
+    use wasmi::{Engine, Module, Store};
+
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f64" (func $sin (param f64) (result f64)))
+            (func (export "sin") (param f64) (result f64)
+                (call $sin (local.get 0))))"#,
+    )
+    .unwrap();
+    let sin = |fuel| {
+        let options = crate::Options {
+            exports: crate::ExportFilter::keep_all(),
+            polyfills: vec![Polyfill::Math],
+            fuel: Some(fuel),
+            max_call_depth: Some(16),
+            canonicalize_nans: true,
+            ..Default::default()
+        };
+        let woven = crate::weave_with(&wasm, &options).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &woven[..]).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = wasmi::Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .unwrap();
+        let sin = instance.get_typed_func::<f64, f64>(&store, "sin").unwrap();
+        sin.call(&mut store, 1e20)
+    };
+
+    // The polyfill is metered, not only the calling function
+    assert!(sin(20).is_err());
+    assert_eq!(sin(100_000).unwrap(), 1e20f64.sin());
+}
+
+#[test]
+fn test_wasi() {
+    use wasmi::{Engine, Linker, Module, Store};
+
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "random_get"
+                (func $random_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\10\00\00\00\05\00\00\00\20\00\00\00\07\00\00\00")
+            (func (export "print") (param i32) (result i32)
+                (drop (call $fd_write (local.get 0) (i32.const 0) (i32.const 2) (i32.const 64)))
+                (i32.load (i32.const 64)))
+            (func (export "random") (result i64)
+                (drop (call $random_get (i32.const 69) (i32.const 13)))
+                (i64.load (i32.const 69)))
+            (func (export "exit") (call $proc_exit (i32.const 0))))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        exports: crate::ExportFilter::keep_all(),
+        polyfills: vec![Polyfill::Wasi],
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = ParsedModule::read(Parser::new(0), &woven).unwrap();
+    assert!(parsed.imports.is_empty());
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &woven[..]).unwrap();
+    let mut store = Store::new(&engine, ());
+    let instance = Linker::new(&engine)
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .unwrap();
+    let print = instance
+        .get_typed_func::<i32, i32>(&store, "print")
+        .unwrap();
+    let random = instance
+        .get_typed_func::<(), i64>(&store, "random")
+        .unwrap();
+    let exit = instance.get_typed_func::<(), ()>(&store, "exit").unwrap();
+
+    assert_eq!(print.call(&mut store, 1).unwrap(), 12);
+    let first = random.call(&mut store, ()).unwrap();
+    let second = random.call(&mut store, ()).unwrap();
+    assert_ne!(first, 0);
+    assert_ne!(first, second);
+    assert!(exit.call(&mut store, ()).is_err());
+
+    let without_memory = wat::parse_str(
+        r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (func (export "exit") (call $proc_exit (i32.const 0))))"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        crate::weave_with(&without_memory, &options),
+        Err(crate::Error::Weave(WeaveError::PolyfillWithoutMemory(_)))
+    ));
+}
@@ -0,0 +1,957 @@
+;; Pure WebAssembly implementations of the host's `math` module.
+;;
+;; The f64 functions follow fdlibm/musl, with a Payne-Hanek reduction for large arguments of
+;; sin, cos and tan in 64-bit integer arithmetic instead of arrays. The f32 functions round the
+;; f64 result. The module has no memory, tables or globals, so its functions can
+;; be added to any module.
+(module
+  ;; Multiplies by 2^n without overflowing in between.
+  (func $scalbn (param $x f64) (param $n i32) (result f64)
+    (if (i32.gt_s (local.get $n) (i32.const 1023))
+      (then
+        (local.set $x (f64.mul (local.get $x) (f64.const 0x1p1023)))
+        (local.set $n (i32.sub (local.get $n) (i32.const 1023)))
+        (if (i32.gt_s (local.get $n) (i32.const 1023))
+          (then
+            (local.set $x (f64.mul (local.get $x) (f64.const 0x1p1023)))
+            (local.set $n (i32.sub (local.get $n) (i32.const 1023)))
+            (if (i32.gt_s (local.get $n) (i32.const 1023))
+              (then (local.set $n (i32.const 1023))))))))
+    (if (i32.lt_s (local.get $n) (i32.const -1022))
+      (then
+        ;; Scaling by 2^-969 keeps 53 bits for the final multiplication
+        (local.set $x (f64.mul (local.get $x) (f64.const 0x1p-969)))
+        (local.set $n (i32.add (local.get $n) (i32.const 969)))
+        (if (i32.lt_s (local.get $n) (i32.const -1022))
+          (then
+            (local.set $x (f64.mul (local.get $x) (f64.const 0x1p-969)))
+            (local.set $n (i32.add (local.get $n) (i32.const 969)))
+            (if (i32.lt_s (local.get $n) (i32.const -1022))
+              (then (local.set $n (i32.const -1022))))))))
+    (f64.mul
+      (local.get $x)
+      (f64.reinterpret_i64
+        (i64.shl
+          (i64.extend_i32_s (i32.add (local.get $n) (i32.const 1023)))
+          (i64.const 52)))))
+
+  (func $exp (param $x f64) (result f64)
+    (local $k i32) (local $kf f64) (local $hi f64) (local $lo f64) (local $r f64)
+    (local $rr f64) (local $c f64) (local $y f64)
+    (if (f64.ne (local.get $x) (local.get $x))
+      (then (return (local.get $x))))
+    (if (f64.gt (local.get $x) (f64.const 709.782712893383973096))
+      (then (return (f64.mul (local.get $x) (f64.const 0x1p1023)))))
+    (if (f64.lt (local.get $x) (f64.const -745.13321910194110842))
+      (then (return (f64.const 0))))
+    ;; x = k*ln2 + r, |r| <= ln2/2
+    (local.set $kf
+      (f64.nearest (f64.mul (local.get $x) (f64.const 1.44269504088896338700e+00))))
+    (local.set $k (i32.trunc_f64_s (local.get $kf)))
+    (local.set $hi
+      (f64.sub (local.get $x) (f64.mul (local.get $kf) (f64.const 6.93147180369123816490e-01))))
+    (local.set $lo (f64.mul (local.get $kf) (f64.const 1.90821492927058770002e-10)))
+    (local.set $r (f64.sub (local.get $hi) (local.get $lo)))
+    (local.set $rr (f64.mul (local.get $r) (local.get $r)))
+    (local.set $c
+      (f64.sub
+        (local.get $r)
+        (f64.mul
+          (local.get $rr)
+          (f64.add (f64.const 1.66666666666666019037e-01)
+            (f64.mul (local.get $rr)
+              (f64.add (f64.const -2.77777777770155933842e-03)
+                (f64.mul (local.get $rr)
+                  (f64.add (f64.const 6.61375632143793436117e-05)
+                    (f64.mul (local.get $rr)
+                      (f64.add (f64.const -1.65339022054652515390e-06)
+                        (f64.mul (local.get $rr) (f64.const 4.13813679705723846039e-08))))))))))))
+    (local.set $y
+      (f64.add
+        (f64.const 1)
+        (f64.add
+          (f64.sub
+            (f64.div
+              (f64.mul (local.get $r) (local.get $c))
+              (f64.sub (f64.const 2) (local.get $c)))
+            (local.get $lo))
+          (local.get $hi))))
+    (call $scalbn (local.get $y) (local.get $k)))
+
+  ;; Splits a positive normal or subnormal x into 2^k * (1 + f) with 1 + f in [sqrt(2)/2, sqrt(2)).
+  (func $log_reduce (param $x f64) (result f64 i32)
+    (local $bits i64) (local $hx i32) (local $k i32)
+    (local.set $bits (i64.reinterpret_f64 (local.get $x)))
+    (local.set $hx (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 32))))
+    (if (i32.lt_u (local.get $hx) (i32.const 0x00100000))
+      (then
+        ;; Subnormal, scale up
+        (local.set $k (i32.const -54))
+        (local.set $bits (i64.reinterpret_f64 (f64.mul (local.get $x) (f64.const 0x1p54))))
+        (local.set $hx (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 32))))))
+    (local.set $hx (i32.add (local.get $hx) (i32.const 0x95f62))) ;; 0x3ff00000 - 0x3fe6a09e
+    (local.set $k
+      (i32.add
+        (local.get $k)
+        (i32.sub (i32.shr_u (local.get $hx) (i32.const 20)) (i32.const 0x3ff))))
+    (local.set $hx
+      (i32.add (i32.and (local.get $hx) (i32.const 0x000fffff)) (i32.const 0x3fe6a09e)))
+    (local.set $bits
+      (i64.or
+        (i64.shl (i64.extend_i32_u (local.get $hx)) (i64.const 32))
+        (i64.and (local.get $bits) (i64.const 0xffffffff))))
+    (f64.sub (f64.reinterpret_i64 (local.get $bits)) (f64.const 1))
+    (local.get $k))
+
+  ;; log(1 + f) - f + f*f/2, for f from $log_reduce.
+  (func $log_poly (param $f f64) (param $hfsq f64) (result f64)
+    (local $s f64) (local $z f64) (local $w f64)
+    (local.set $s (f64.div (local.get $f) (f64.add (f64.const 2) (local.get $f))))
+    (local.set $z (f64.mul (local.get $s) (local.get $s)))
+    (local.set $w (f64.mul (local.get $z) (local.get $z)))
+    (f64.mul
+      (local.get $s)
+      (f64.add
+        (local.get $hfsq)
+        (f64.add
+          (f64.mul (local.get $z)
+            (f64.add (f64.const 6.666666666666735130e-01)
+              (f64.mul (local.get $w)
+                (f64.add (f64.const 2.857142874366239149e-01)
+                  (f64.mul (local.get $w)
+                    (f64.add (f64.const 1.818357216161805012e-01)
+                      (f64.mul (local.get $w) (f64.const 1.479819860511658591e-01))))))))
+          (f64.mul (local.get $w)
+            (f64.add (f64.const 3.999999999940941908e-01)
+              (f64.mul (local.get $w)
+                (f64.add (f64.const 2.222219843214978396e-01)
+                  (f64.mul (local.get $w) (f64.const 1.531383769920937332e-01))))))))))
+
+  ;; Returns the result for zero, negative, infinite and NaN arguments of log and log10.
+  (func $log_special (param $x f64) (result f64 i32)
+    (if (f64.eq (local.get $x) (f64.const 0))
+      (then (return (f64.const -inf) (i32.const 1))))
+    (if (i64.lt_s (i64.reinterpret_f64 (local.get $x)) (i64.const 0))
+      (then (return (f64.const nan) (i32.const 1))))
+    (if (i32.or
+          (f64.ne (local.get $x) (local.get $x))
+          (f64.eq (local.get $x) (f64.const inf)))
+      (then (return (local.get $x) (i32.const 1))))
+    (f64.const 0)
+    (i32.const 0))
+
+  (func $log (param $x f64) (result f64)
+    (local $f f64) (local $k i32) (local $hfsq f64) (local $dk f64)
+    (call $log_special (local.get $x))
+    (if (param f64) (result f64)
+      (then (return)))
+    (drop)
+    (call $log_reduce (local.get $x))
+    (local.set $k)
+    (local.set $f)
+    (local.set $hfsq (f64.mul (f64.mul (f64.const 0.5) (local.get $f)) (local.get $f)))
+    (local.set $dk (f64.convert_i32_s (local.get $k)))
+    (f64.add
+      (f64.add
+        (f64.sub
+          (f64.add
+            (call $log_poly (local.get $f) (local.get $hfsq))
+            (f64.mul (local.get $dk) (f64.const 1.90821492927058770002e-10)))
+          (local.get $hfsq))
+        (local.get $f))
+      (f64.mul (local.get $dk) (f64.const 6.93147180369123816490e-01))))
+
+  ;; log(x) as an unevaluated sum hi + lo, for positive finite x.
+  (func $log_extended (param $x f64) (result f64 f64)
+    (local $f f64) (local $k i32) (local $hfsq f64) (local $dk f64) (local $hi f64)
+    (local $lo f64) (local $a f64) (local $s f64) (local $bb f64)
+    (call $log_reduce (local.get $x))
+    (local.set $k)
+    (local.set $f)
+    (local.set $hfsq (f64.mul (f64.mul (f64.const 0.5) (local.get $f)) (local.get $f)))
+    (local.set $dk (f64.convert_i32_s (local.get $k)))
+    ;; f - hfsq with the low 32 bits cleared, so the sums below are exact
+    (local.set $hi
+      (f64.reinterpret_i64
+        (i64.and
+          (i64.reinterpret_f64 (f64.sub (local.get $f) (local.get $hfsq)))
+          (i64.const 0xffffffff00000000))))
+    (local.set $lo
+      (f64.add
+        (f64.sub (f64.sub (local.get $f) (local.get $hi)) (local.get $hfsq))
+        (call $log_poly (local.get $f) (local.get $hfsq))))
+    ;; Two-sum of k*ln2_hi and hi
+    (local.set $a (f64.mul (local.get $dk) (f64.const 6.93147180369123816490e-01)))
+    (local.set $s (f64.add (local.get $a) (local.get $hi)))
+    (local.set $bb (f64.sub (local.get $s) (local.get $a)))
+    (local.set $lo
+      (f64.add
+        (f64.add
+          (f64.sub (local.get $a) (f64.sub (local.get $s) (local.get $bb)))
+          (f64.sub (local.get $hi) (local.get $bb)))
+        (f64.add
+          (f64.mul (local.get $dk) (f64.const 1.90821492927058770002e-10))
+          (local.get $lo))))
+    (local.set $hi (f64.add (local.get $s) (local.get $lo)))
+    (local.get $hi)
+    (f64.sub (local.get $lo) (f64.sub (local.get $hi) (local.get $s))))
+
+  (func $log10 (param $x f64) (result f64)
+    (local $f f64) (local $k i32) (local $hfsq f64) (local $dk f64) (local $hi f64)
+    (local $lo f64) (local $val_hi f64) (local $val_lo f64) (local $y f64) (local $w f64)
+    (call $log_special (local.get $x))
+    (if (param f64) (result f64)
+      (then (return)))
+    (drop)
+    (call $log_reduce (local.get $x))
+    (local.set $k)
+    (local.set $f)
+    (local.set $hfsq (f64.mul (f64.mul (f64.const 0.5) (local.get $f)) (local.get $f)))
+    (local.set $dk (f64.convert_i32_s (local.get $k)))
+    (local.set $hi
+      (f64.reinterpret_i64
+        (i64.and
+          (i64.reinterpret_f64 (f64.sub (local.get $f) (local.get $hfsq)))
+          (i64.const 0xffffffff00000000))))
+    (local.set $lo
+      (f64.add
+        (f64.sub (f64.sub (local.get $f) (local.get $hi)) (local.get $hfsq))
+        (call $log_poly (local.get $f) (local.get $hfsq))))
+    (local.set $val_hi (f64.mul (local.get $hi) (f64.const 4.34294481878168880939e-01)))
+    (local.set $y (f64.mul (local.get $dk) (f64.const 3.01029995663611771306e-01)))
+    (local.set $val_lo
+      (f64.add
+        (f64.add
+          (f64.mul (local.get $dk) (f64.const 3.69423907715893078616e-13))
+          (f64.mul
+            (f64.add (local.get $lo) (local.get $hi))
+            (f64.const 2.50829467116452752298e-11)))
+        (f64.mul (local.get $lo) (f64.const 4.34294481878168880939e-01))))
+    (local.set $w (f64.add (local.get $y) (local.get $val_hi)))
+    (local.set $val_lo
+      (f64.add
+        (local.get $val_lo)
+        (f64.add (f64.sub (local.get $y) (local.get $w)) (local.get $val_hi))))
+    (f64.add (local.get $val_lo) (local.get $w)))
+
+  ;; Whether y is an odd integer.
+  (func $is_odd (param $y f64) (result i32)
+    (i32.and
+      (f64.lt (f64.abs (local.get $y)) (f64.const 0x1p53))
+      (i32.and
+        (f64.eq (f64.trunc (local.get $y)) (local.get $y))
+        (f64.ne
+          (f64.mul (f64.trunc (f64.mul (local.get $y) (f64.const 0.5))) (f64.const 2))
+          (local.get $y)))))
+
+  (func $pow (param $x f64) (param $y f64) (result f64)
+    (local $ax f64) (local $negative i32) (local $hi f64) (local $lo f64) (local $p f64)
+    (local $err f64) (local $c f64) (local $ahi f64) (local $alo f64) (local $bhi f64)
+    (local $blo f64) (local $r f64)
+    (if (i32.or
+          (f64.eq (local.get $y) (f64.const 0))
+          (f64.eq (local.get $x) (f64.const 1)))
+      (then (return (f64.const 1))))
+    (if (i32.or
+          (f64.ne (local.get $x) (local.get $x))
+          (f64.ne (local.get $y) (local.get $y)))
+      (then (return (f64.add (local.get $x) (local.get $y)))))
+    (local.set $ax (f64.abs (local.get $x)))
+    (if (i32.and
+          (f64.eq (local.get $ax) (f64.const 1))
+          (f64.eq (f64.abs (local.get $y)) (f64.const inf)))
+      (then (return (f64.const 1))))
+    (if (i64.lt_s (i64.reinterpret_f64 (local.get $x)) (i64.const 0))
+      (then
+        (if (i32.and
+              (f64.ne (f64.trunc (local.get $y)) (local.get $y))
+              (i32.and
+                (f64.ne (local.get $ax) (f64.const 0))
+                (f64.ne (local.get $ax) (f64.const inf))))
+          (then (return (f64.const nan))))
+        (local.set $negative (call $is_odd (local.get $y)))))
+    (if (f64.eq (local.get $ax) (f64.const 0))
+      (then
+        (local.set $r
+          (select (f64.const inf) (f64.const 0) (f64.lt (local.get $y) (f64.const 0)))))
+      (else
+        (if (f64.eq (local.get $ax) (f64.const inf))
+          (then
+            (local.set $r
+              (select (f64.const 0) (f64.const inf) (f64.lt (local.get $y) (f64.const 0)))))
+          (else
+            ;; exp(y * log|x|), with the product in extended precision
+            (call $log_extended (local.get $ax))
+            (local.set $lo)
+            (local.set $hi)
+            (local.set $p (f64.mul (local.get $y) (local.get $hi)))
+            (if (i32.and
+                  (f64.lt (f64.abs (local.get $p)) (f64.const 1024))
+                  (f64.lt (f64.abs (local.get $y)) (f64.const 0x1p996)))
+              (then
+                ;; Dekker's exact product
+                (local.set $c (f64.mul (local.get $y) (f64.const 134217729)))
+                (local.set $ahi (f64.sub (local.get $c) (f64.sub (local.get $c) (local.get $y))))
+                (local.set $alo (f64.sub (local.get $y) (local.get $ahi)))
+                (local.set $c (f64.mul (local.get $hi) (f64.const 134217729)))
+                (local.set $bhi (f64.sub (local.get $c) (f64.sub (local.get $c) (local.get $hi))))
+                (local.set $blo (f64.sub (local.get $hi) (local.get $bhi)))
+                (local.set $err
+                  (f64.add
+                    (f64.add
+                      (f64.add
+                        (f64.sub (f64.mul (local.get $ahi) (local.get $bhi)) (local.get $p))
+                        (f64.mul (local.get $ahi) (local.get $blo)))
+                      (f64.mul (local.get $alo) (local.get $bhi)))
+                    (f64.mul (local.get $alo) (local.get $blo))))
+                (local.set $err
+                  (f64.add (local.get $err) (f64.mul (local.get $y) (local.get $lo))))))
+            (local.set $r (call $exp (local.get $p)))
+            ;; inf * 0 would be NaN on overflow
+            (if (f64.lt (local.get $r) (f64.const inf))
+              (then
+                (local.set $r
+                  (f64.add (local.get $r) (f64.mul (local.get $r) (local.get $err))))))))))
+    (select (f64.neg (local.get $r)) (local.get $r) (local.get $negative)))
+
+  ;; Word k of the binary fraction of 2/pi, enough for the exponents of doubles.
+  (func $two_over_pi (param $k i32) (result i64)
+    (block $20 (block $19 (block $18 (block $17 (block $16 (block $15 (block $14
+    (block $13 (block $12 (block $11 (block $10 (block $9 (block $8 (block $7
+    (block $6 (block $5 (block $4 (block $3 (block $2 (block $1 (block $0
+      (br_table $0 $1 $2 $3 $4 $5 $6 $7 $8 $9 $10 $11 $12 $13 $14 $15 $16 $17 $18 $19 $20
+        (local.get $k)))
+      (return (i64.const 0xa2f9836e4e441529)))
+      (return (i64.const 0xfc2757d1f534ddc0)))
+      (return (i64.const 0xdb6295993c439041)))
+      (return (i64.const 0xfe5163abdebbc561)))
+      (return (i64.const 0xb7246e3a424dd2e0)))
+      (return (i64.const 0x6492eea09d1921c)))
+      (return (i64.const 0xfe1deb1cb129a73e)))
+      (return (i64.const 0xe88235f52ebb4484)))
+      (return (i64.const 0xe99c7026b45f7e41)))
+      (return (i64.const 0x3991d639835339f4)))
+      (return (i64.const 0x9c845f8bbdf9283b)))
+      (return (i64.const 0x1ff897ffde05980f)))
+      (return (i64.const 0xef2f118b5a0a6d1f)))
+      (return (i64.const 0x6d367ecf27cb09b7)))
+      (return (i64.const 0x4f463f669e5fea2d)))
+      (return (i64.const 0x7527bac7ebe5f17b)))
+      (return (i64.const 0x3d0739f78a5292ea)))
+      (return (i64.const 0x6bfb5fb11f8d5d08)))
+      (return (i64.const 0x56033046fc7b6bab)))
+      (return (i64.const 0xf0cfbc209af4361d)))
+    (i64.const 0))
+
+  ;; 64 bits of 2/pi starting after bit s, for s >= -63.
+  (func $two_over_pi_bits (param $s i32) (result i64)
+    (local $k i32) (local $r i64)
+    (if (i32.lt_s (local.get $s) (i32.const 0))
+      (then
+        (return
+          (i64.shr_u
+            (call $two_over_pi (i32.const 0))
+            (i64.extend_i32_u (i32.sub (i32.const 0) (local.get $s)))))))
+    (local.set $k (i32.shr_u (local.get $s) (i32.const 6)))
+    (local.set $r (i64.extend_i32_u (i32.and (local.get $s) (i32.const 63))))
+    (if (i64.eqz (local.get $r))
+      (then (return (call $two_over_pi (local.get $k)))))
+    (i64.or
+      (i64.shl (call $two_over_pi (local.get $k)) (local.get $r))
+      (i64.shr_u
+        (call $two_over_pi (i32.add (local.get $k) (i32.const 1)))
+        (i64.sub (i64.const 64) (local.get $r)))))
+
+  ;; Unsigned 128-bit product as high and low words.
+  (func $mul_wide (param $a i64) (param $b i64) (result i64 i64)
+    (local $a0 i64) (local $a1 i64) (local $b0 i64) (local $b1 i64)
+    (local $p00 i64) (local $p01 i64) (local $p10 i64) (local $mid i64)
+    (local.set $a0 (i64.and (local.get $a) (i64.const 0xffffffff)))
+    (local.set $a1 (i64.shr_u (local.get $a) (i64.const 32)))
+    (local.set $b0 (i64.and (local.get $b) (i64.const 0xffffffff)))
+    (local.set $b1 (i64.shr_u (local.get $b) (i64.const 32)))
+    (local.set $p00 (i64.mul (local.get $a0) (local.get $b0)))
+    (local.set $p01 (i64.mul (local.get $a0) (local.get $b1)))
+    (local.set $p10 (i64.mul (local.get $a1) (local.get $b0)))
+    (local.set $mid
+      (i64.add
+        (i64.add
+          (i64.shr_u (local.get $p00) (i64.const 32))
+          (i64.and (local.get $p01) (i64.const 0xffffffff)))
+        (i64.and (local.get $p10) (i64.const 0xffffffff))))
+    (i64.add
+      (i64.add
+        (i64.mul (local.get $a1) (local.get $b1))
+        (i64.add
+          (i64.shr_u (local.get $p01) (i64.const 32))
+          (i64.shr_u (local.get $p10) (i64.const 32))))
+      (i64.shr_u (local.get $mid) (i64.const 32)))
+    (i64.or
+      (i64.shl (local.get $mid) (i64.const 32))
+      (i64.and (local.get $p00) (i64.const 0xffffffff))))
+
+  ;; a * b as the rounded product and its exact error (Dekker).
+  (func $two_prod (param $a f64) (param $b f64) (result f64 f64)
+    (local $p f64) (local $c f64)
+    (local $ahi f64) (local $alo f64) (local $bhi f64) (local $blo f64)
+    (local.set $p (f64.mul (local.get $a) (local.get $b)))
+    (local.set $c (f64.mul (local.get $a) (f64.const 134217729)))
+    (local.set $ahi (f64.sub (local.get $c) (f64.sub (local.get $c) (local.get $a))))
+    (local.set $alo (f64.sub (local.get $a) (local.get $ahi)))
+    (local.set $c (f64.mul (local.get $b) (f64.const 134217729)))
+    (local.set $bhi (f64.sub (local.get $c) (f64.sub (local.get $c) (local.get $b))))
+    (local.set $blo (f64.sub (local.get $b) (local.get $bhi)))
+    (local.get $p)
+    (f64.add
+      (f64.add
+        (f64.add
+          (f64.sub (f64.mul (local.get $ahi) (local.get $bhi)) (local.get $p))
+          (f64.mul (local.get $ahi) (local.get $blo)))
+        (f64.mul (local.get $alo) (local.get $bhi)))
+      (f64.mul (local.get $alo) (local.get $blo))))
+
+  ;; rem_pio2 for finite |x| >= 2^20 * pi/2 (Payne-Hanek). The mantissa is multiplied with the
+  ;; 192 bits of 2/pi that affect x * 2/pi mod 4, giving the quadrant and a 128-bit fraction.
+  (func $rem_pio2_large (param $x f64) (result i32 f64 f64)
+    (local $bits i64) (local $m i64) (local $s i32) (local $hc i64) (local $lc i64)
+    (local $hb i64) (local $lb i64) (local $w1 i64) (local $w2 i64) (local $n i32)
+    (local $fh i64) (local $fl i64) (local $f3 i64) (local $negative i32)
+    (local $shift i64) (local $lz i64)
+    (local $hi f64) (local $lo f64) (local $scale f64) (local $p f64) (local $err f64)
+    (local $t f64) (local $y0 f64)
+    (local.set $bits (i64.reinterpret_f64 (local.get $x)))
+    (local.set $m
+      (i64.or (i64.and (local.get $bits) (i64.const 0xfffffffffffff)) (i64.const 0x10000000000000)))
+    ;; x = m * 2^e, bits of 2/pi up to s = e - 2 only add multiples of 4
+    (local.set $s
+      (i32.sub (call $exponent (local.get $x)) (i32.const 1077)))
+    (call $mul_wide
+      (local.get $m) (call $two_over_pi_bits (i32.add (local.get $s) (i32.const 128))))
+    (local.set $lc)
+    (local.set $hc)
+    (call $mul_wide (local.get $m) (call $two_over_pi_bits (i32.add (local.get $s) (i32.const 64))))
+    (local.set $lb)
+    (local.set $hb)
+    ;; The product has the binary point after bit 190, 2 integer bits below it are kept
+    (local.set $w2 (i64.add (local.get $hc) (local.get $lb)))
+    (local.set $w1
+      (i64.add
+        (i64.add
+          (local.get $hb)
+          (i64.mul (local.get $m) (call $two_over_pi_bits (local.get $s))))
+        (i64.extend_i32_u (i64.lt_u (local.get $w2) (local.get $hc)))))
+    (local.set $n (i32.wrap_i64 (i64.shr_u (local.get $w1) (i64.const 62))))
+    (local.set $fh
+      (i64.or (i64.shl (local.get $w1) (i64.const 2)) (i64.shr_u (local.get $w2) (i64.const 62))))
+    (local.set $fl
+      (i64.or (i64.shl (local.get $w2) (i64.const 2)) (i64.shr_u (local.get $lc) (i64.const 62))))
+    (local.set $f3 (i64.shl (local.get $lc) (i64.const 2)))
+    ;; Round to the nearest quadrant, the fraction becomes 1 - f
+    (if (i64.lt_s (local.get $fh) (i64.const 0))
+      (then
+        (local.set $n (i32.add (local.get $n) (i32.const 1)))
+        (local.set $negative (i32.const 1))
+        (local.set $fh
+          (i64.add
+            (i64.xor (local.get $fh) (i64.const -1))
+            (i64.extend_i32_u (i64.eqz (i64.or (local.get $fl) (local.get $f3))))))
+        (local.set $fl
+          (i64.add
+            (i64.xor (local.get $fl) (i64.const -1))
+            (i64.extend_i32_u (i64.eqz (local.get $f3)))))
+        (local.set $f3 (i64.sub (i64.const 0) (local.get $f3)))))
+    ;; Up to 61 leading bits cancel for doubles near multiples of pi/2
+    (if (i64.eqz (local.get $fh))
+      (then
+        (local.set $fh (local.get $fl))
+        (local.set $fl (local.get $f3))
+        (local.set $shift (i64.const 64))))
+    ;; Normalize and split into a double-double
+    (local.set $lz (i64.clz (local.get $fh)))
+    (if (i64.ne (local.get $lz) (i64.const 0))
+      (then
+        (local.set $fh
+          (i64.or
+            (i64.shl (local.get $fh) (local.get $lz))
+            (i64.shr_u (local.get $fl) (i64.sub (i64.const 64) (local.get $lz)))))
+        (local.set $fl
+          (i64.or
+            (i64.shl (local.get $fl) (local.get $lz))
+            (i64.shr_u (local.get $f3) (i64.sub (i64.const 64) (local.get $lz)))))))
+    (local.set $shift (i64.add (local.get $shift) (local.get $lz)))
+    (local.set $scale
+      (f64.reinterpret_i64
+        (i64.shl (i64.sub (i64.const 970) (local.get $shift)) (i64.const 52)))) ;; 2^(-53 - shift)
+    (local.set $hi
+      (f64.mul (f64.convert_i64_u (i64.shr_u (local.get $fh) (i64.const 11))) (local.get $scale)))
+    (local.set $lo
+      (f64.mul
+        (f64.mul
+          (f64.convert_i64_u
+            (i64.or
+              (i64.shl (i64.and (local.get $fh) (i64.const 0x7ff)) (i64.const 53))
+              (i64.shr_u (local.get $fl) (i64.const 11))))
+          (f64.const 0x1p-64))
+        (local.get $scale)))
+    ;; Multiply by pi/2 in extended precision
+    (call $two_prod (local.get $hi) (f64.const 1.57079632679489655800e+00))
+    (local.set $err)
+    (local.set $p)
+    (local.set $t
+      (f64.add
+        (local.get $err)
+        (f64.add
+          (f64.mul (local.get $hi) (f64.const 6.12323399573676603587e-17))
+          (f64.mul (local.get $lo) (f64.const 1.57079632679489655800e+00)))))
+    (local.set $y0 (f64.add (local.get $p) (local.get $t)))
+    (local.set $t (f64.sub (local.get $t) (f64.sub (local.get $y0) (local.get $p))))
+    (if (i32.ne (local.get $negative) (f64.lt (local.get $x) (f64.const 0)))
+      (then
+        (local.set $y0 (f64.neg (local.get $y0)))
+        (local.set $t (f64.neg (local.get $t)))))
+    (select
+      (i32.sub (i32.const 0) (local.get $n)) (local.get $n) (f64.lt (local.get $x) (f64.const 0)))
+    (local.get $y0)
+    (local.get $t))
+
+  ;; Reduces x to n*pi/2 + (y0 + y1) with |y0 + y1| <= pi/4.
+  (func $rem_pio2 (param $x f64) (result i32 f64 f64)
+    (local $fn f64) (local $r f64) (local $w f64) (local $y0 f64) (local $t f64) (local $ex i32)
+    (if (f64.le (f64.abs (local.get $x)) (f64.const 0.785398163397448279))
+      (then (return (i32.const 0) (local.get $x) (f64.const 0))))
+    (if (f64.ne (f64.sub (local.get $x) (local.get $x)) (f64.const 0))
+      (then (return (i32.const 0) (f64.sub (local.get $x) (local.get $x)) (f64.const 0))))
+    (if (f64.ge (f64.abs (local.get $x)) (f64.const 0x1.921fbp+20))
+      (then (return (call $rem_pio2_large (local.get $x)))))
+    (local.set $fn
+      (f64.nearest (f64.mul (local.get $x) (f64.const 6.36619772367581382433e-01))))
+    (local.set $r
+      (f64.sub (local.get $x) (f64.mul (local.get $fn) (f64.const 1.57079632673412561417e+00))))
+    (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050650619224932e-11)))
+    (local.set $y0 (f64.sub (local.get $r) (local.get $w)))
+    (local.set $ex
+      (i32.wrap_i64
+        (i64.and (i64.shr_u (i64.reinterpret_f64 (local.get $x)) (i64.const 52)) (i64.const 0x7ff))))
+    (if (i32.gt_s
+          (i32.sub (local.get $ex) (call $exponent (local.get $y0)))
+          (i32.const 16))
+      (then
+        (local.set $t (local.get $r))
+        (local.set $w (f64.mul (local.get $fn) (f64.const 6.07710050630396597660e-11)))
+        (local.set $r (f64.sub (local.get $t) (local.get $w)))
+        (local.set $w
+          (f64.sub
+            (f64.mul (local.get $fn) (f64.const 2.02226624879595063154e-21))
+            (f64.sub (f64.sub (local.get $t) (local.get $r)) (local.get $w))))
+        (local.set $y0 (f64.sub (local.get $r) (local.get $w)))
+        (if (i32.gt_s
+              (i32.sub (local.get $ex) (call $exponent (local.get $y0)))
+              (i32.const 49))
+          (then
+            (local.set $t (local.get $r))
+            (local.set $w (f64.mul (local.get $fn) (f64.const 2.02226624871116645580e-21)))
+            (local.set $r (f64.sub (local.get $t) (local.get $w)))
+            (local.set $w
+              (f64.sub
+                (f64.mul (local.get $fn) (f64.const 8.47842766036889956997e-32))
+                (f64.sub (f64.sub (local.get $t) (local.get $r)) (local.get $w))))
+            (local.set $y0 (f64.sub (local.get $r) (local.get $w)))))))
+    (i32.wrap_i64 (i64.trunc_sat_f64_s (local.get $fn)))
+    (local.get $y0)
+    (f64.sub (f64.sub (local.get $r) (local.get $y0)) (local.get $w)))
+
+  ;; Biased exponent.
+  (func $exponent (param $x f64) (result i32)
+    (i32.wrap_i64
+      (i64.and (i64.shr_u (i64.reinterpret_f64 (local.get $x)) (i64.const 52)) (i64.const 0x7ff))))
+
+  ;; sin(x + y) for |x + y| <= pi/4.
+  (func $sin_kernel (param $x f64) (param $y f64) (result f64)
+    (local $z f64) (local $w f64) (local $r f64) (local $v f64)
+    (local.set $z (f64.mul (local.get $x) (local.get $x)))
+    (local.set $w (f64.mul (local.get $z) (local.get $z)))
+    (local.set $r
+      (f64.add
+        (f64.add (f64.const 8.33333333332248946124e-03)
+          (f64.mul (local.get $z)
+            (f64.add (f64.const -1.98412698298579493134e-04)
+              (f64.mul (local.get $z) (f64.const 2.75573137070700676789e-06)))))
+        (f64.mul (f64.mul (local.get $z) (local.get $w))
+          (f64.add (f64.const -2.50507602534068634195e-08)
+            (f64.mul (local.get $z) (f64.const 1.58969099521155010221e-10))))))
+    (local.set $v (f64.mul (local.get $z) (local.get $x)))
+    (f64.sub
+      (local.get $x)
+      (f64.sub
+        (f64.sub
+          (f64.mul
+            (local.get $z)
+            (f64.sub
+              (f64.mul (f64.const 0.5) (local.get $y))
+              (f64.mul (local.get $v) (local.get $r))))
+          (local.get $y))
+        (f64.mul (local.get $v) (f64.const -1.66666666666666324348e-01)))))
+
+  ;; cos(x + y) for |x + y| <= pi/4.
+  (func $cos_kernel (param $x f64) (param $y f64) (result f64)
+    (local $z f64) (local $w f64) (local $r f64) (local $hz f64)
+    (local.set $z (f64.mul (local.get $x) (local.get $x)))
+    (local.set $w (f64.mul (local.get $z) (local.get $z)))
+    (local.set $r
+      (f64.add
+        (f64.mul (local.get $z)
+          (f64.add (f64.const 4.16666666666666019037e-02)
+            (f64.mul (local.get $z)
+              (f64.add (f64.const -1.38888888888741095749e-03)
+                (f64.mul (local.get $z) (f64.const 2.48015872894767294178e-05))))))
+        (f64.mul (f64.mul (local.get $w) (local.get $w))
+          (f64.add (f64.const -2.75573143513906633035e-07)
+            (f64.mul (local.get $z)
+              (f64.add (f64.const 2.08757232129817482790e-09)
+                (f64.mul (local.get $z) (f64.const -1.13596475577881948265e-11))))))))
+    (local.set $hz (f64.mul (f64.const 0.5) (local.get $z)))
+    (local.set $w (f64.sub (f64.const 1) (local.get $hz)))
+    (f64.add
+      (local.get $w)
+      (f64.add
+        (f64.sub (f64.sub (f64.const 1) (local.get $w)) (local.get $hz))
+        (f64.sub
+          (f64.mul (local.get $z) (local.get $r))
+          (f64.mul (local.get $x) (local.get $y))))))
+
+  (func $sin (param $x f64) (result f64)
+    (local $n i32) (local $y0 f64) (local $y1 f64)
+    (if (f64.eq (f64.abs (local.get $x)) (f64.const inf))
+      (then (return (f64.const nan))))
+    (call $rem_pio2 (local.get $x))
+    (local.set $y1)
+    (local.set $y0)
+    (local.set $n)
+    (block $3 (block $2 (block $1 (block $0
+      (br_table $0 $1 $2 $3 (i32.and (local.get $n) (i32.const 3))))
+      (return (call $sin_kernel (local.get $y0) (local.get $y1))))
+      (return (call $cos_kernel (local.get $y0) (local.get $y1))))
+      (return (f64.neg (call $sin_kernel (local.get $y0) (local.get $y1)))))
+    (f64.neg (call $cos_kernel (local.get $y0) (local.get $y1))))
+
+  (func $cos (param $x f64) (result f64)
+    (local $n i32) (local $y0 f64) (local $y1 f64)
+    (if (f64.eq (f64.abs (local.get $x)) (f64.const inf))
+      (then (return (f64.const nan))))
+    (call $rem_pio2 (local.get $x))
+    (local.set $y1)
+    (local.set $y0)
+    (local.set $n)
+    (block $3 (block $2 (block $1 (block $0
+      (br_table $0 $1 $2 $3 (i32.and (local.get $n) (i32.const 3))))
+      (return (call $cos_kernel (local.get $y0) (local.get $y1))))
+      (return (f64.neg (call $sin_kernel (local.get $y0) (local.get $y1)))))
+      (return (f64.neg (call $cos_kernel (local.get $y0) (local.get $y1)))))
+    (call $sin_kernel (local.get $y0) (local.get $y1)))
+
+  (func $tan (param $x f64) (result f64)
+    (local $n i32) (local $y0 f64) (local $y1 f64) (local $s f64) (local $c f64)
+    (if (f64.eq (f64.abs (local.get $x)) (f64.const inf))
+      (then (return (f64.const nan))))
+    (call $rem_pio2 (local.get $x))
+    (local.set $y1)
+    (local.set $y0)
+    (local.set $n)
+    (local.set $s (call $sin_kernel (local.get $y0) (local.get $y1)))
+    (local.set $c (call $cos_kernel (local.get $y0) (local.get $y1)))
+    (if (result f64) (i32.and (local.get $n) (i32.const 1))
+      (then (f64.neg (f64.div (local.get $c) (local.get $s))))
+      (else (f64.div (local.get $s) (local.get $c)))))
+
+  (func $atan (param $x f64) (result f64)
+    (local $ax f64) (local $id i32) (local $z f64) (local $w f64) (local $s f64)
+    (local $hi f64) (local $lo f64)
+    (if (f64.ne (local.get $x) (local.get $x))
+      (then (return (local.get $x))))
+    (local.set $ax (f64.abs (local.get $x)))
+    (if (f64.ge (local.get $ax) (f64.const 0x1p66))
+      (then
+        (return
+          (f64.copysign (f64.const 1.57079632679489655800e+00) (local.get $x)))))
+    (if (f64.lt (local.get $ax) (f64.const 0.4375))
+      (then
+        (if (f64.lt (local.get $ax) (f64.const 0x1p-27))
+          (then (return (local.get $x))))
+        (local.set $id (i32.const -1))
+        (local.set $ax (local.get $x)))
+      (else
+        (if (f64.lt (local.get $ax) (f64.const 1.1875))
+          (then
+            (if (f64.lt (local.get $ax) (f64.const 0.6875))
+              (then
+                (local.set $id (i32.const 0))
+                (local.set $ax
+                  (f64.div
+                    (f64.sub (f64.mul (f64.const 2) (local.get $ax)) (f64.const 1))
+                    (f64.add (f64.const 2) (local.get $ax)))))
+              (else
+                (local.set $id (i32.const 1))
+                (local.set $ax
+                  (f64.div
+                    (f64.sub (local.get $ax) (f64.const 1))
+                    (f64.add (local.get $ax) (f64.const 1)))))))
+          (else
+            (if (f64.lt (local.get $ax) (f64.const 2.4375))
+              (then
+                (local.set $id (i32.const 2))
+                (local.set $ax
+                  (f64.div
+                    (f64.sub (local.get $ax) (f64.const 1.5))
+                    (f64.add (f64.const 1) (f64.mul (f64.const 1.5) (local.get $ax))))))
+              (else
+                (local.set $id (i32.const 3))
+                (local.set $ax (f64.div (f64.const -1) (local.get $ax)))))))))
+    (local.set $z (f64.mul (local.get $ax) (local.get $ax)))
+    (local.set $w (f64.mul (local.get $z) (local.get $z)))
+    (local.set $s
+      (f64.add
+        (f64.mul (local.get $z)
+          (f64.add (f64.const 3.33333333333329318027e-01)
+            (f64.mul (local.get $w)
+              (f64.add (f64.const 1.42857142725034663711e-01)
+                (f64.mul (local.get $w)
+                  (f64.add (f64.const 9.09088713343650656196e-02)
+                    (f64.mul (local.get $w)
+                      (f64.add (f64.const 6.66107313738753120669e-02)
+                        (f64.mul (local.get $w)
+                          (f64.add (f64.const 4.97687799461593236017e-02)
+                            (f64.mul (local.get $w) (f64.const 1.62858201153657823623e-02))))))))))))
+        (f64.mul (local.get $w)
+          (f64.add (f64.const -1.99999999998764832476e-01)
+            (f64.mul (local.get $w)
+              (f64.add (f64.const -1.11111104054623557880e-01)
+                (f64.mul (local.get $w)
+                  (f64.add (f64.const -7.69187620504482999495e-02)
+                    (f64.mul (local.get $w)
+                      (f64.add (f64.const -5.83357013379057348645e-02)
+                        (f64.mul (local.get $w) (f64.const -3.65315727442169155270e-02))))))))))))
+    (if (i32.lt_s (local.get $id) (i32.const 0))
+      (then
+        (return
+          (f64.sub (local.get $ax) (f64.mul (local.get $ax) (local.get $s))))))
+    ;; atan of the reduction points 0.5, 1, 1.5 and infinity, in two parts
+    (block $3 (block $2 (block $1 (block $0
+      (br_table $0 $1 $2 $3 (local.get $id)))
+      (local.set $hi (f64.const 4.63647609000806093515e-01))
+      (local.set $lo (f64.const 2.26987774529616870924e-17))
+      (br $3))
+      (local.set $hi (f64.const 7.85398163397448278999e-01))
+      (local.set $lo (f64.const 3.06161699786838301793e-17))
+      (br $3))
+      (local.set $hi (f64.const 9.82793723247329054082e-01))
+      (local.set $lo (f64.const 1.39033110312309984516e-17))
+      (br $3))
+    (if (i32.eq (local.get $id) (i32.const 3))
+      (then
+        (local.set $hi (f64.const 1.57079632679489655800e+00))
+        (local.set $lo (f64.const 6.12323399573676603587e-17))))
+    (f64.copysign
+      (f64.sub
+        (local.get $hi)
+        (f64.sub
+          (f64.sub (f64.mul (local.get $ax) (local.get $s)) (local.get $lo))
+          (local.get $ax)))
+      (local.get $x)))
+
+  (func $atan2 (param $y f64) (param $x f64) (result f64)
+    (local $ay f64) (local $ax f64) (local $x_negative i32) (local $z f64)
+    (if (i32.or
+          (f64.ne (local.get $x) (local.get $x))
+          (f64.ne (local.get $y) (local.get $y)))
+      (then (return (f64.add (local.get $x) (local.get $y)))))
+    (if (f64.eq (local.get $x) (f64.const 1))
+      (then (return (call $atan (local.get $y)))))
+    (local.set $ay (f64.abs (local.get $y)))
+    (local.set $ax (f64.abs (local.get $x)))
+    (local.set $x_negative (i64.lt_s (i64.reinterpret_f64 (local.get $x)) (i64.const 0)))
+    (if (f64.eq (local.get $ay) (f64.const 0))
+      (then
+        (return
+          (select
+            (f64.copysign (f64.const 3.1415926535897931160E+00) (local.get $y))
+            (local.get $y)
+            (local.get $x_negative)))))
+    (if (i32.or
+          (f64.eq (local.get $ax) (f64.const 0))
+          (i32.and
+            (f64.eq (local.get $ay) (f64.const inf))
+            (f64.ne (local.get $ax) (f64.const inf))))
+      (then
+        (return (f64.copysign (f64.const 1.57079632679489661923) (local.get $y)))))
+    (if (f64.eq (local.get $ax) (f64.const inf))
+      (then
+        (if (f64.eq (local.get $ay) (f64.const inf))
+          (then
+            (return
+              (f64.copysign
+                (select
+                  (f64.const 2.35619449019234492885)
+                  (f64.const 0.785398163397448309616)
+                  (local.get $x_negative))
+                (local.get $y)))))
+        (return
+          (f64.copysign
+            (select (f64.const 3.1415926535897931160E+00) (f64.const 0) (local.get $x_negative))
+            (local.get $y)))))
+    (if (f64.gt (local.get $ay) (f64.mul (local.get $ax) (f64.const 0x1p64)))
+      (then
+        (return (f64.copysign (f64.const 1.57079632679489661923) (local.get $y)))))
+    (if (i32.and
+          (local.get $x_negative)
+          (f64.lt (f64.mul (local.get $ay) (f64.const 0x1p64)) (local.get $ax)))
+      (then (local.set $z (f64.const 0)))
+      (else (local.set $z (call $atan (f64.div (local.get $ay) (local.get $ax))))))
+    (if (local.get $x_negative)
+      (then
+        ;; pi - z, with pi in two parts
+        (local.set $z
+          (f64.sub
+            (f64.const 3.1415926535897931160E+00)
+            (f64.sub (local.get $z) (f64.const 1.2246467991473531772E-16))))))
+    (f64.copysign (local.get $z) (local.get $y)))
+
+  (func $asin (param $x f64) (result f64)
+    (call $atan2
+      (local.get $x)
+      (f64.sqrt
+        (f64.mul
+          (f64.sub (f64.const 1) (local.get $x))
+          (f64.add (f64.const 1) (local.get $x))))))
+
+  (func $acos (param $x f64) (result f64)
+    (call $atan2
+      (f64.sqrt
+        (f64.mul
+          (f64.sub (f64.const 1) (local.get $x))
+          (f64.add (f64.const 1) (local.get $x))))
+      (local.get $x)))
+
+  ;; sinh(x) for |x| < 0.5, by its Taylor series.
+  (func $sinh_series (param $x f64) (result f64)
+    (local $t f64) (local $s f64)
+    (local.set $t (f64.mul (local.get $x) (local.get $x)))
+    (local.set $s (f64.add (f64.const 1) (f64.div (local.get $t) (f64.const 272))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 210)) (local.get $s))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 156)) (local.get $s))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 110)) (local.get $s))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 72)) (local.get $s))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 42)) (local.get $s))))
+    (local.set $s
+      (f64.add (f64.const 1) (f64.mul (f64.div (local.get $t) (f64.const 20)) (local.get $s))))
+    (f64.add
+      (local.get $x)
+      (f64.mul
+        (local.get $x)
+        (f64.mul (f64.div (local.get $t) (f64.const 6)) (local.get $s)))))
+
+  (func $sinh (param $x f64) (result f64)
+    (local $ax f64) (local $e f64)
+    (local.set $ax (f64.abs (local.get $x)))
+    (if (f64.lt (local.get $ax) (f64.const 0.5))
+      (then (return (call $sinh_series (local.get $x)))))
+    (if (f64.lt (local.get $ax) (f64.const 709))
+      (then
+        (local.set $e (call $exp (local.get $ax)))
+        (return
+          (f64.copysign
+            (f64.mul
+              (f64.const 0.5)
+              (f64.sub (local.get $e) (f64.div (f64.const 1) (local.get $e))))
+            (local.get $x)))))
+    ;; exp(|x|) overflows before sinh does
+    (local.set $e (call $exp (f64.mul (f64.const 0.5) (local.get $ax))))
+    (f64.copysign
+      (f64.mul (f64.mul (f64.const 0.5) (local.get $e)) (local.get $e))
+      (local.get $x)))
+
+  (func $cosh (param $x f64) (result f64)
+    (local $ax f64) (local $e f64)
+    (local.set $ax (f64.abs (local.get $x)))
+    (if (f64.lt (local.get $ax) (f64.const 709))
+      (then
+        (local.set $e (call $exp (local.get $ax)))
+        (return
+          (f64.mul
+            (f64.const 0.5)
+            (f64.add (local.get $e) (f64.div (f64.const 1) (local.get $e)))))))
+    (local.set $e (call $exp (f64.mul (f64.const 0.5) (local.get $ax))))
+    (f64.mul (f64.mul (f64.const 0.5) (local.get $e)) (local.get $e)))
+
+  (func $tanh (param $x f64) (result f64)
+    (local $ax f64) (local $r f64)
+    (local.set $ax (f64.abs (local.get $x)))
+    (if (f64.gt (local.get $ax) (f64.const 22))
+      (then (local.set $r (f64.const 1)))
+      (else
+        (if (f64.lt (local.get $ax) (f64.const 0.5))
+          (then
+            (local.set $r
+              (f64.div (call $sinh_series (local.get $ax)) (call $cosh (local.get $ax)))))
+          (else
+            (local.set $r
+              (f64.sub
+                (f64.const 1)
+                (f64.div
+                  (f64.const 2)
+                  (f64.add (call $exp (f64.mul (f64.const 2) (local.get $ax))) (f64.const 1)))))))))
+    (f64.copysign (local.get $r) (local.get $x)))
+
+  (func (export "sin_f64") (param f64) (result f64) (call $sin (local.get 0)))
+  (func (export "cos_f64") (param f64) (result f64) (call $cos (local.get 0)))
+  (func (export "tan_f64") (param f64) (result f64) (call $tan (local.get 0)))
+  (func (export "asin_f64") (param f64) (result f64) (call $asin (local.get 0)))
+  (func (export "acos_f64") (param f64) (result f64) (call $acos (local.get 0)))
+  (func (export "atan_f64") (param f64) (result f64) (call $atan (local.get 0)))
+  (func (export "atan2_f64") (param f64 f64) (result f64)
+    (call $atan2 (local.get 0) (local.get 1)))
+  (func (export "sinh_f64") (param f64) (result f64) (call $sinh (local.get 0)))
+  (func (export "cosh_f64") (param f64) (result f64) (call $cosh (local.get 0)))
+  (func (export "tanh_f64") (param f64) (result f64) (call $tanh (local.get 0)))
+  (func (export "sqrt_f64") (param f64) (result f64) (f64.sqrt (local.get 0)))
+  (func (export "log_f64") (param f64) (result f64) (call $log (local.get 0)))
+  (func (export "log10_f64") (param f64) (result f64) (call $log10 (local.get 0)))
+  (func (export "exp_f64") (param f64) (result f64) (call $exp (local.get 0)))
+  (func (export "pow_f64") (param f64 f64) (result f64)
+    (call $pow (local.get 0) (local.get 1)))
+
+  (func (export "sin_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $sin (f64.promote_f32 (local.get 0)))))
+  (func (export "cos_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $cos (f64.promote_f32 (local.get 0)))))
+  (func (export "tan_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $tan (f64.promote_f32 (local.get 0)))))
+  (func (export "asin_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $asin (f64.promote_f32 (local.get 0)))))
+  (func (export "acos_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $acos (f64.promote_f32 (local.get 0)))))
+  (func (export "atan_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $atan (f64.promote_f32 (local.get 0)))))
+  (func (export "atan2_f32") (param f32 f32) (result f32)
+    (f32.demote_f64
+      (call $atan2 (f64.promote_f32 (local.get 0)) (f64.promote_f32 (local.get 1)))))
+  (func (export "sinh_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $sinh (f64.promote_f32 (local.get 0)))))
+  (func (export "cosh_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $cosh (f64.promote_f32 (local.get 0)))))
+  (func (export "tanh_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $tanh (f64.promote_f32 (local.get 0)))))
+  (func (export "sqrt_f32") (param f32) (result f32) (f32.sqrt (local.get 0)))
+  (func (export "log_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $log (f64.promote_f32 (local.get 0)))))
+  (func (export "log10_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $log10 (f64.promote_f32 (local.get 0)))))
+  (func (export "exp_f32") (param f32) (result f32)
+    (f32.demote_f64 (call $exp (f64.promote_f32 (local.get 0)))))
+  (func (export "pow_f32") (param f32 f32) (result f32)
+    (f32.demote_f64
+      (call $pow (f64.promote_f32 (local.get 0)) (f64.promote_f32 (local.get 1))))))
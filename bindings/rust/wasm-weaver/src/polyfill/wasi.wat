@@ -0,0 +1,176 @@
+;; wasi_snapshot_preview1 for modules without a file system, clock or process.
+;; The memory is the memory of the woven module.
+(module
+  (import "env" "memory" (memory 0))
+
+  ;; splitmix64 state, the same on every client
+  (global $random (mut i64) (i64.const 0x5265736f6e697465))
+
+  ;; Output to stdout and stderr is discarded, since the host has no log.
+  (func (export "fd_write")
+    (param $fd i32) (param $iovs i32) (param $iovs_len i32) (param $nwritten i32) (result i32)
+    (local $total i32)
+    (if (i32.and (i32.ne (local.get $fd) (i32.const 1)) (i32.ne (local.get $fd) (i32.const 2)))
+      (then (return (i32.const 8)))) ;; badf
+    (block $done
+      (loop $iov
+        (br_if $done (i32.eqz (local.get $iovs_len)))
+        (local.set $total
+          (i32.add (local.get $total) (i32.load offset=4 (local.get $iovs))))
+        (local.set $iovs (i32.add (local.get $iovs) (i32.const 8)))
+        (local.set $iovs_len (i32.sub (local.get $iovs_len) (i32.const 1)))
+        (br $iov)))
+    (i32.store (local.get $nwritten) (local.get $total))
+    (i32.const 0))
+
+  ;; stdin is empty.
+  (func (export "fd_read")
+    (param $fd i32) (param $iovs i32) (param $iovs_len i32) (param $nread i32) (result i32)
+    (if (local.get $fd)
+      (then (return (i32.const 8))))
+    (i32.store (local.get $nread) (i32.const 0))
+    (i32.const 0))
+
+  (func (export "fd_fdstat_get") (param $fd i32) (param $stat i32) (result i32)
+    (if (i32.gt_u (local.get $fd) (i32.const 2))
+      (then (return (i32.const 8))))
+    (i32.store8 (local.get $stat) (i32.const 2)) ;; character device
+    (i32.store16 offset=2 (local.get $stat) (i32.const 0))
+    ;; fd_read or fd_write
+    (i64.store offset=8 (local.get $stat)
+      (select (i64.const 0x2) (i64.const 0x40) (i32.eqz (local.get $fd))))
+    (i64.store offset=16 (local.get $stat) (i64.const 0))
+    (i32.const 0))
+
+  (func (export "fd_close") (param $fd i32) (result i32)
+    (select (i32.const 0) (i32.const 8) (i32.le_u (local.get $fd) (i32.const 2))))
+
+  (func (export "fd_seek")
+    (param $fd i32) (param $offset i64) (param $whence i32) (param $new_offset i32) (result i32)
+    (select (i32.const 70) (i32.const 8) (i32.le_u (local.get $fd) (i32.const 2)))) ;; spipe
+
+  ;; There are no preopened directories, so there are no other files.
+  (func (export "fd_prestat_get") (param i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_prestat_dir_name") (param i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_advise") (param i32 i64 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_allocate") (param i32 i64 i64) (result i32)
+    (i32.const 8))
+  (func (export "fd_datasync") (param i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_fdstat_set_flags") (param i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_fdstat_set_rights") (param i32 i64 i64) (result i32)
+    (i32.const 8))
+  (func (export "fd_filestat_get") (param i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_filestat_set_size") (param i32 i64) (result i32)
+    (i32.const 8))
+  (func (export "fd_filestat_set_times") (param i32 i64 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_pread") (param i32 i32 i32 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_pwrite") (param i32 i32 i32 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_readdir") (param i32 i32 i32 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_renumber") (param i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_sync") (param i32) (result i32)
+    (i32.const 8))
+  (func (export "fd_tell") (param i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_create_directory") (param i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_filestat_get") (param i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_filestat_set_times") (param i32 i32 i32 i32 i64 i64 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_link") (param i32 i32 i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_open")
+    (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_readlink") (param i32 i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_remove_directory") (param i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_rename") (param i32 i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_symlink") (param i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "path_unlink_file") (param i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "sock_accept") (param i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "sock_recv") (param i32 i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "sock_send") (param i32 i32 i32 i32 i32) (result i32)
+    (i32.const 8))
+  (func (export "sock_shutdown") (param i32 i32) (result i32)
+    (i32.const 8))
+
+  ;; No arguments and no environment variables.
+  (func (export "args_sizes_get") (param $count i32) (param $size i32) (result i32)
+    (i32.store (local.get $count) (i32.const 0))
+    (i32.store (local.get $size) (i32.const 0))
+    (i32.const 0))
+  (func (export "args_get") (param i32 i32) (result i32)
+    (i32.const 0))
+  (func (export "environ_sizes_get") (param $count i32) (param $size i32) (result i32)
+    (i32.store (local.get $count) (i32.const 0))
+    (i32.store (local.get $size) (i32.const 0))
+    (i32.const 0))
+  (func (export "environ_get") (param i32 i32) (result i32)
+    (i32.const 0))
+
+  ;; Every clock stands still at 0, so clients agree on the time.
+  (func (export "clock_time_get")
+    (param $id i32) (param $precision i64) (param $time i32) (result i32)
+    (if (i32.gt_u (local.get $id) (i32.const 3))
+      (then (return (i32.const 28)))) ;; inval
+    (i64.store (local.get $time) (i64.const 0))
+    (i32.const 0))
+  (func (export "clock_res_get") (param $id i32) (param $resolution i32) (result i32)
+    (if (i32.gt_u (local.get $id) (i32.const 3))
+      (then (return (i32.const 28))))
+    (i64.store (local.get $resolution) (i64.const 1))
+    (i32.const 0))
+
+  (func (export "random_get") (param $buf i32) (param $len i32) (result i32)
+    (local $i i32) (local $bits i64)
+    (block $done
+      (loop $byte
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.eqz (i32.and (local.get $i) (i32.const 7)))
+          (then (local.set $bits (call $next_random))))
+        (i64.store8 (i32.add (local.get $buf) (local.get $i)) (local.get $bits))
+        (local.set $bits (i64.shr_u (local.get $bits) (i64.const 8)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $byte)))
+    (i32.const 0))
+
+  (func $next_random (result i64)
+    (local $z i64)
+    (global.set $random (i64.add (global.get $random) (i64.const 0x9e3779b97f4a7c15)))
+    (local.set $z (global.get $random))
+    (local.set $z
+      (i64.mul
+        (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 30)))
+        (i64.const 0xbf58476d1ce4e5b9)))
+    (local.set $z
+      (i64.mul
+        (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 27)))
+        (i64.const 0x94d049bb133111eb)))
+    (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 31))))
+
+  (func (export "poll_oneoff") (param i32 i32 i32 i32) (result i32)
+    (i32.const 52)) ;; nosys
+  (func (export "sched_yield") (result i32)
+    (i32.const 0))
+  (func (export "proc_raise") (param i32) (result i32)
+    (i32.const 52))
+  (func (export "proc_exit") (param i32)
+    (unreachable)))
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use serde_json::json;
+use wasm_weaver::{DceStats, Lint};
+
+use crate::args::MessageFormat;
+
+pub fn count(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {noun}"),
+        n => format!("{n} {noun}s"),
+    }
+}
+
+/// Sizes of a woven artifact.
+pub struct WeaveSummary {
+    pub input_size: usize,
+    pub output_size: usize,
+    pub exports: usize,
+    /// What dead-code elimination removed, if enabled.
+    pub dce: Option<DceStats>,
+}
+
+/// Reports weaver events, either for humans or as JSON lines next to cargo's messages.
+#[derive(Debug, Clone, Copy)]
+pub struct Reporter {
+    pub format: MessageFormat,
+}
+
+impl Reporter {
+    pub fn weave_started(&self, name: &str, input: &Path) {
+        match self.format {
+            MessageFormat::Human => eprintln!("Weaving {name}"),
+            MessageFormat::Json => println!(
+                "{}",
+                json!({
+                    "reason": "weave-started",
+                    "name": name,
+                    "input": input,
+                })
+            ),
+        }
+    }
+
+    pub fn lint(&self, name: &str, level: &str, lint: &Lint) {
+        match self.format {
+            MessageFormat::Human => eprintln!("{level}: {lint}"),
+            MessageFormat::Json => println!(
+                "{}",
+                json!({
+                    "reason": "lint-finding",
+                    "name": name,
+                    "level": level,
+                    "message": lint.to_string(),
+                })
+            ),
+        }
+    }
+
+    pub fn weave_finished(
+        &self,
+        name: &str,
+        output: &Path,
+        result: Result<&WeaveSummary, &dyn std::error::Error>,
+    ) {
+        match (self.format, result) {
+            (MessageFormat::Human, Ok(summary)) => {
+                print!(
+                    "{}: {} -> {} bytes, {} exports",
+                    output.display(),
+                    summary.input_size,
+                    summary.output_size,
+                    summary.exports
+                );
+                match summary.dce {
+                    Some(dce) => println!(
+                        ", removed {} and {} ({} bytes of code)",
+                        count(dce.functions, "function"),
+                        count(dce.globals, "global"),
+                        dce.code_bytes
+                    ),
+                    None => println!(),
+                }
+            }
+            (MessageFormat::Human, Err(error)) => eprintln!("error: {name}: {error}"),
+            (MessageFormat::Json, Ok(summary)) => println!(
+                "{}",
+                json!({
+                    "reason": "weave-finished",
+                    "name": name,
+                    "output": output,
+                    "success": true,
+                    "input-size": summary.input_size,
+                    "output-size": summary.output_size,
+                    "exports": summary.exports,
+                    "dce": summary.dce.map(|dce| json!({
+                        "functions": dce.functions,
+                        "globals": dce.globals,
+                        "code-bytes": dce.code_bytes,
+                    })),
+                })
+            ),
+            (MessageFormat::Json, Err(error)) => println!(
+                "{}",
+                json!({
+                    "reason": "weave-finished",
+                    "name": name,
+                    "output": output,
+                    "success": false,
+                    "error": error.to_string(),
+                })
+            ),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Multi-value exports for any language, declared in a custom section.
+//!
+//! The `frooxengine.returns` section is a JSON object mapping export names to their results
+//! and how they are passed, e.g.
+//! `{"position": {"results": ["f32", "f32", "f32"], "passing": "return-pointer"}}`.
+//! With `return-pointer`, the export returns a pointer to the results, laid out like a C
+//! struct. Weaving exports a wrapper instead which loads them, and removes the section.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use wasm_encoder::{Function, Instruction, ValType};
+use wasmparser::{FuncType, KnownCustom};
+
+use crate::{
+    import_results::{self, Field},
+    parse::ParsedModule,
+    weaver::WeaveError,
+};
+
+pub const SECTION: &str = "frooxengine.returns";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Returns {
+    pub results: Vec<Field>,
+    pub passing: Passing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Passing {
+    /// The function returns a pointer to the results in memory.
+    ReturnPointer,
+}
+
+/// Reads the returns of all sections by export name.
+pub fn read(parsed: &ParsedModule) -> Result<HashMap<String, Returns>, WeaveError> {
+    let mut returns = HashMap::new();
+    for custom in &parsed.custom {
+        if custom.name() != SECTION || !matches!(custom.as_known(), KnownCustom::Unknown) {
+            continue;
+        }
+        let section = serde_json::from_slice::<HashMap<String, Returns>>(custom.data())
+            .map_err(|error| WeaveError::InvalidReturnsSection(error.to_string()))?;
+        returns.extend(section);
+    }
+    Ok(returns)
+}
+
+impl Returns {
+    /// Checks that the function returns what the declared passing expects.
+    pub fn check(&self, export: &str, ty: &FuncType) -> Result<(), WeaveError> {
+        match (self.passing, ty.results()) {
+            (Passing::ReturnPointer, [wasmparser::ValType::I32]) => Ok(()),
+            _ => Err(WeaveError::InvalidReturnsSection(format!(
+                "export {export:?} doesn't return a pointer"
+            ))),
+        }
+    }
+
+    pub fn val_types(&self) -> Vec<wasmparser::ValType> {
+        self.results.iter().map(|field| field.val_type()).collect()
+    }
+
+    /// Calls the function and loads the results from the returned pointer.
+    pub fn wrapper_body(&self, params: u32, callee: u32) -> Function {
+        let mut func = Function::new([(1, ValType::I32)]);
+        for local in 0..params {
+            func.instruction(&Instruction::LocalGet(local));
+        }
+        func.instruction(&Instruction::Call(callee))
+            .instruction(&Instruction::LocalSet(params));
+        for (field, offset) in import_results::layout(&self.results) {
+            func.instruction(&Instruction::LocalGet(params))
+                .instruction(&field.load(offset));
+        }
+        func.instruction(&Instruction::End);
+        func
+    }
+}
+
+#[test]
+fn test_wrapper_export() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (func $position (export "position") (param i32) (result i32)
+                i32.const 16)
+            (func (export "other") (result i32)
+                i32.const 0
+                call $position)
+            (memory (export "memory") 1)
+            (@custom "frooxengine.returns"
+                "{\"position\": {\"results\": [\"f32\", \"i8\", \"f64\"], \"passing\": \"return-pointer\"}}"))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        exports: crate::ExportFilter::keep_all(),
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let text = crate::text::print(&woven, &Default::default()).unwrap();
+
+    assert!(text.contains(r#"(export "position" (func 2))"#), "{text}");
+    assert!(text.contains("(func (;2;) (type 2) (param i32) (result f32 i32 f64)"));
+    assert!(text.contains("i32.load8_s offset=4"));
+    assert!(text.contains("f64.load offset=8"));
+    assert!(!text.contains(SECTION));
+}
@@ -0,0 +1,391 @@
+//! Build-time pre-initialization, like Wizer.
+//!
+//! The init export of a woven module runs in an interpreter with mock `math` and `string`
+//! imports. The weaver then writes the resulting memory back as active data segments and
+//! the mutable globals as initializers, and drops the init export and the start function,
+//! which already ran. Other host imports trap when called.
+
+use std::{collections::HashMap, convert::Infallible};
+
+use wasm_encoder::{
+    ConstExpr, ExportKind, ExportSection, Ieee32, Ieee64, Module,
+    reencode::{self, Reencode, RoundtripReencoder, utils},
+};
+use wasmi::{Caller, Engine, Extern, ExternRef, Linker, Store, Val};
+use wasmparser::{ExportSectionReader, TypeRef};
+
+use crate::parse::ParsedModule;
+
+const MEMORY_EXPORT: &str = "__snapshot_memory";
+const GLOBAL_EXPORT_PREFIX: &str = "__snapshot_global_";
+/// Zero runs shorter than this don't split data segments, which cost about as much.
+const MIN_GAP: usize = 8;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("no exported function named `{0}`")]
+    MissingInit(String),
+    #[error("init function `{0}` must not have parameters")]
+    InitParams(String),
+    #[error("import `{module}.{name}` can't be provided at build time, only functions can")]
+    UnsupportedImport { module: String, name: String },
+    #[error("global {0} holds a reference after init, which can't be snapshotted")]
+    Reference(u32),
+    #[error("failed to instantiate module: {0}")]
+    Instantiate(wasmi::Error),
+    #[error("init function trapped: {0}")]
+    Trap(wasmi::Error),
+}
+
+/// State of a module after running its init export.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub init: String,
+    /// Contents of the defined memory
+    pub memory: Option<Vec<u8>>,
+    /// Values of the mutable defined globals, by index including imports
+    pub globals: HashMap<u32, ConstExpr>,
+}
+
+impl Snapshot {
+    /// Instantiates the module, which runs the start function, and calls `init`.
+    pub fn take(parsed: &ParsedModule, wasm: &[u8], init: &str) -> Result<Self, SnapshotError> {
+        if !parsed.exports.iter().any(|export| export.name == init) {
+            return Err(SnapshotError::MissingInit(init.to_string()));
+        }
+        let mut globals = Vec::new();
+        for import in &parsed.imports {
+            match import.ty {
+                TypeRef::Func(_) => (),
+                TypeRef::Global(_) => globals.push(None),
+                _ => {
+                    return Err(SnapshotError::UnsupportedImport {
+                        module: import.module.to_string(),
+                        name: import.name.to_string(),
+                    });
+                }
+            }
+        }
+        globals.extend(parsed.globals.iter().map(|global| Some(global.ty)));
+
+        // Globals and memories are only accessible through exports
+        let mut exported = ExportState {
+            globals: (globals.iter().enumerate())
+                .filter(|(_, ty)| ty.is_some_and(|ty| ty.mutable))
+                .map(|(index, _)| index as u32)
+                .collect(),
+            memory: !parsed.memories.is_empty(),
+        };
+        let mut module = Module::new();
+        exported
+            .parse_core_module(&mut module, wasmparser::Parser::new(0), wasm)
+            .map_err(|error| SnapshotError::Instantiate(wasmi::Error::new(error.to_string())))?;
+        let wasm = module.finish();
+
+        let engine = Engine::default();
+        let module = wasmi::Module::new(&engine, &wasm[..]).map_err(SnapshotError::Instantiate)?;
+        let mut store = Store::new(&engine, ());
+        let mut linker = Linker::new(&engine);
+        for import in module.imports() {
+            let defined = match import.module() {
+                "math" => define_math(&mut linker, import.name()),
+                "string" => define_string(&mut linker, import.name()),
+                _ => Ok(false),
+            };
+            if defined.map_err(SnapshotError::Instantiate)? {
+                continue;
+            }
+            let (module, name) = (import.module().to_string(), import.name().to_string());
+            let Some(ty) = import.ty().func().cloned() else {
+                return Err(SnapshotError::UnsupportedImport { module, name });
+            };
+            let message = format!("host import `{module}.{name}` is not available at build time");
+            linker
+                .func_new(&module, &name, ty, move |_, _, _| {
+                    Err(wasmi::Error::new(message.clone()))
+                })
+                .map_err(|error| SnapshotError::Instantiate(error.into()))?;
+        }
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(SnapshotError::Instantiate)?;
+        let func = instance
+            .get_func(&store, init)
+            .ok_or_else(|| SnapshotError::MissingInit(init.to_string()))?;
+        let ty = func.ty(&store);
+        if !ty.params().is_empty() {
+            return Err(SnapshotError::InitParams(init.to_string()));
+        }
+        let mut results = ty
+            .results()
+            .iter()
+            .copied()
+            .map(Val::default)
+            .collect::<Vec<_>>();
+        func.call(&mut store, &[], &mut results)
+            .map_err(SnapshotError::Trap)?;
+
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT)
+            .map(|memory| memory.data(&store).to_vec());
+        let mut values = HashMap::new();
+        for index in exported.globals {
+            let name = format!("{GLOBAL_EXPORT_PREFIX}{index}");
+            let global = instance
+                .get_global(&store, &name)
+                .expect("global is exported");
+            let value = match global.get(&store) {
+                Val::I32(value) => ConstExpr::i32_const(value),
+                Val::I64(value) => ConstExpr::i64_const(value),
+                Val::F32(value) => ConstExpr::f32_const(Ieee32::new(value.to_bits())),
+                Val::F64(value) => ConstExpr::f64_const(Ieee64::new(value.to_bits())),
+                Val::FuncRef(value) if value.is_null() => null(&globals, index)?,
+                Val::ExternRef(value) if value.is_null() => null(&globals, index)?,
+                Val::FuncRef(_) | Val::ExternRef(_) => return Err(SnapshotError::Reference(index)),
+            };
+            values.insert(index, value);
+        }
+
+        Ok(Self {
+            init: init.to_string(),
+            memory,
+            globals: values,
+        })
+    }
+
+    /// Offsets and contents of the data segments for the non-zero bytes of the memory.
+    pub fn segments(&self) -> Vec<(u32, &[u8])> {
+        let Some(memory) = &self.memory else {
+            return Vec::new();
+        };
+        let mut segments = Vec::new();
+        let mut start = None;
+        let mut zeros = 0;
+        for (offset, &byte) in memory.iter().enumerate() {
+            match (byte, start) {
+                (0, Some(_)) => zeros += 1,
+                (0, None) => (),
+                (_, None) => start = Some(offset),
+                (_, Some(_)) => zeros = 0,
+            }
+            if let Some(segment) = start
+                && zeros == MIN_GAP
+            {
+                segments.push((segment as u32, &memory[segment..offset + 1 - zeros]));
+                start = None;
+                zeros = 0;
+            }
+        }
+        if let Some(segment) = start {
+            segments.push((segment as u32, &memory[segment..memory.len() - zeros]));
+        }
+        segments
+    }
+
+    /// Size of the memory after init, in pages.
+    pub fn memory_pages(&self) -> Option<u64> {
+        Some(self.memory.as_ref()?.len() as u64 / 65536)
+    }
+}
+
+fn null(
+    globals: &[Option<wasmparser::GlobalType>],
+    index: u32,
+) -> Result<ConstExpr, SnapshotError> {
+    let ty = globals[index as usize].expect("only defined globals are snapshotted");
+    let wasmparser::ValType::Ref(ty) = ty.content_type else {
+        unreachable!("reference values have a reference type");
+    };
+    let heap_type = RoundtripReencoder
+        .heap_type(ty.heap_type())
+        .map_err(|_| SnapshotError::Reference(index))?;
+    Ok(ConstExpr::ref_null(heap_type))
+}
+
+/// Exports the memory and mutable globals under internal names.
+struct ExportState {
+    globals: Vec<u32>,
+    memory: bool,
+}
+
+impl Reencode for ExportState {
+    type Error = Infallible;
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: ExportSectionReader<'_>,
+    ) -> Result<(), reencode::Error<Self::Error>> {
+        utils::parse_export_section(self, exports, section)?;
+        for &global in &self.globals {
+            let name = format!("{GLOBAL_EXPORT_PREFIX}{global}");
+            exports.export(&name, ExportKind::Global, global);
+        }
+        if self.memory {
+            exports.export(MEMORY_EXPORT, ExportKind::Memory, 0);
+        }
+        Ok(())
+    }
+}
+
+/// Defines a `math` import with the Rust implementation, which may differ from the host in
+/// the last bits.
+fn define_math(linker: &mut Linker<()>, name: &str) -> Result<bool, wasmi::Error> {
+    let Some((function, float)) = name.rsplit_once('_') else {
+        return Ok(false);
+    };
+    macro_rules! define {
+        ($f32:expr, $f64:expr) => {
+            match float {
+                "f32" => linker.func_wrap("math", name, $f32)?,
+                "f64" => linker.func_wrap("math", name, $f64)?,
+                _ => return Ok(false),
+            }
+        };
+    }
+    match function {
+        "acos" => define!(f32::acos, f64::acos),
+        "asin" => define!(f32::asin, f64::asin),
+        "atan" => define!(f32::atan, f64::atan),
+        "atan2" => define!(f32::atan2, f64::atan2),
+        "cos" => define!(f32::cos, f64::cos),
+        "cosh" => define!(f32::cosh, f64::cosh),
+        "exp" => define!(f32::exp, f64::exp),
+        "log" => define!(f32::ln, f64::ln),
+        "log10" => define!(f32::log10, f64::log10),
+        "pow" => define!(f32::powf, f64::powf),
+        "sin" => define!(f32::sin, f64::sin),
+        "sinh" => define!(f32::sinh, f64::sinh),
+        "sqrt" => define!(f32::sqrt, f64::sqrt),
+        "tan" => define!(f32::tan, f64::tan),
+        "tanh" => define!(f32::tanh, f64::tanh),
+        _ => return Ok(false),
+    };
+    Ok(true)
+}
+
+/// Defines a `string` import like the host, with strings as WTF-16 code units.
+fn define_string(linker: &mut Linker<()>, name: &str) -> Result<bool, wasmi::Error> {
+    const ERROR_NULL: i32 = -1;
+    const ERROR_MEMORY: i32 = -2;
+
+    fn memory(caller: &Caller<()>) -> Option<wasmi::Memory> {
+        caller.get_export("memory").and_then(Extern::into_memory)
+    }
+    fn units<'a>(caller: &'a Caller<()>, string: &ExternRef) -> Option<&'a Vec<u16>> {
+        string.data(caller)?.downcast_ref()
+    }
+
+    match name {
+        "len_wtf16" => {
+            linker.func_wrap("string", name, |caller: Caller<()>, string: ExternRef| {
+                units(&caller, &string).map_or(ERROR_NULL, |units| units.len() as i32)
+            })?
+        }
+        "read_wtf16" => linker.func_wrap(
+            "string",
+            name,
+            |mut caller: Caller<()>, string: ExternRef, read_len: i32, ptr: i32, ptr_len: i32| {
+                let Some(units) = units(&caller, &string).cloned() else {
+                    return ERROR_NULL;
+                };
+                if read_len <= 0 {
+                    return 0;
+                }
+                let len = units.len().min(ptr_len.max(0) as usize);
+                let Some(memory) = memory(&caller) else {
+                    return ERROR_MEMORY;
+                };
+                let bytes = units[..len].iter().flat_map(|unit| unit.to_le_bytes());
+                let data = memory.data_mut(&mut caller);
+                let Some(target) = data
+                    .get_mut(ptr as u32 as usize..)
+                    .and_then(|data| data.get_mut(..len * 2))
+                else {
+                    return ERROR_MEMORY;
+                };
+                for (target, byte) in target.iter_mut().zip(bytes) {
+                    *target = byte;
+                }
+                len as i32
+            },
+        )?,
+        "new_wtf16" => linker.func_wrap(
+            "string",
+            name,
+            |mut caller: Caller<()>, ptr: i32, len: i32| {
+                let units = memory(&caller).and_then(|memory| {
+                    let data = memory.data(&caller);
+                    let bytes = data
+                        .get(ptr as u32 as usize..)?
+                        .get(..len as u32 as usize * 2)?;
+                    Some(
+                        bytes
+                            .chunks_exact(2)
+                            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                            .collect::<Vec<_>>(),
+                    )
+                });
+                ExternRef::new::<Vec<u16>>(&mut caller, units)
+            },
+        )?,
+        _ => return Ok(false),
+    };
+    Ok(true)
+}
+
+#[test]
+fn test_snapshot() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sqrt_f32" (func $sqrt (param f32) (result f32)))
+            (import "string" "new_wtf16" (func $new (param i32 i32) (result externref)))
+            (import "string" "len_wtf16" (func $len (param externref) (result i32)))
+            (global $ready (mut i32) (i32.const 0))
+            (global $scale (mut f32) (f32.const 0))
+            (func $start
+                f32.const 2
+                global.set $scale)
+            (func $init (export "init")
+                i32.const 16
+                i32.const 16
+                i32.const 2
+                call $new
+                call $len
+                i32.store
+                i32.const 1
+                global.set $ready
+                global.get $scale
+                call $sqrt
+                global.set $scale)
+            (func (export "ready") (result i32 f32)
+                global.get $ready
+                global.get $scale)
+            (memory (export "memory") 1)
+            (start $start)
+            (data (i32.const 16) "h\00i\00"))"#,
+    )
+    .unwrap();
+
+    let woven = crate::snapshot(&wasm, "init").unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &woven).unwrap();
+    let exports = parsed
+        .exports
+        .iter()
+        .map(|export| export.name)
+        .collect::<Vec<_>>();
+    assert_eq!(exports, ["ready", "memory"]);
+    assert_eq!(parsed.start, None);
+    // `$start` and `$init` are removed with their imports
+    assert_eq!((parsed.imports.len(), parsed.code.len()), (0, 1));
+
+    let text = crate::text::print(&woven, &Default::default()).unwrap();
+    assert!(
+        text.contains("(global (;0;) (mut i32) i32.const 1)"),
+        "{text}"
+    );
+    assert!(text.contains("f32.const 0x1.6a09e6p+0"), "{text}");
+    assert!(text.contains(r#"(i32.const 16) "\02")"#), "{text}");
+}
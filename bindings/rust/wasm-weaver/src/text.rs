@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+
+use wasm_encoder::{Module, RawSection};
+use wasmparser::{BinaryReaderError, Parser, Payload};
+
+use crate::Error;
+
+/// Options for [`print`].
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    /// Prints instructions as folded S-expressions.
+    pub fold: bool,
+    /// Keeps the `name` section for `$identifiers`, it is dropped otherwise.
+    pub names: bool,
+}
+
+/// Parses WebAssembly text. Binary modules are returned as is.
+pub fn parse(input: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    Ok(wat::parse_bytes(input)?)
+}
+
+/// Prints a module as WebAssembly text, e.g. for the `TextWebAssemblyModule` component.
+/// Custom sections other than `name` are omitted.
+pub fn print(wasm: &[u8], options: &PrintOptions) -> Result<String, Error> {
+    let wasm = without_custom_sections(wasm, options.names).map_err(Error::Parse)?;
+
+    let mut config = wasmprinter::Config::new();
+    config.fold_instructions(options.fold);
+    let mut text = String::new();
+    config
+        .print(&wasm, &mut wasmprinter::PrintFmtWrite(&mut text))
+        .map_err(|e| Error::Print(e.to_string()))?;
+    Ok(text)
+}
+
+fn without_custom_sections(wasm: &[u8], keep_names: bool) -> Result<Vec<u8>, BinaryReaderError> {
+    let mut module = Module::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+        if let Payload::CustomSection(section) = &payload
+            && !(keep_names && section.name() == "name")
+        {
+            continue;
+        }
+        // Function bodies are part of the code section range
+        if let Some((id, range)) = payload.as_section() {
+            module.section(&RawSection {
+                id,
+                data: &wasm[range],
+            });
+        }
+    }
+    Ok(module.finish())
+}
+
+#[test]
+fn test_print_roundtrip() {
+    let wasm = parse(
+        br#"(module $m
+            (func $add (export "add") (param $a i32) (param $b i32) (result i32)
+                local.get $a
+                local.get $b
+                i32.add)
+            (@custom "producers" "\00"))"#,
+    )
+    .unwrap();
+
+    let folded = print(
+        &wasm,
+        &PrintOptions {
+            fold: true,
+            names: true,
+        },
+    )
+    .unwrap();
+    assert!(folded.contains("(i32.add\n"), "{folded}");
+    assert!(folded.contains("$add"));
+    assert!(!folded.contains("producers"));
+
+    let plain = print(&wasm, &PrintOptions::default()).unwrap();
+    assert!(!plain.contains("$add"));
+    let reparsed = parse(plain.as_bytes()).unwrap();
+    let parsed = crate::parse::ParsedModule::read(Parser::new(0), &reparsed).unwrap();
+    assert_eq!(parsed.exports[0].name, "add");
+    assert!(parsed.custom.is_empty());
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use wasm_weaver::{diff, parse::ParsedModule, text};
+use wasmparser::Parser;
+
+use crate::{
+    args::{BuildArgs, WatchArgs},
+    cargo,
+};
+
+pub fn watch(args: WatchArgs) -> Result<(), Box<dyn Error>> {
+    let paths = cargo::watch_paths(&args.build)?;
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for source in &paths.sources {
+        watcher.watch(source, RecursiveMode::Recursive)?;
+    }
+
+    let mut ignored = vec![paths.target_dir];
+    ignored.extend(args.build.output.iter().chain(&args.build.out_dir).cloned());
+    let ignored = ignored
+        .iter()
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()?;
+    let sources = paths
+        .sources
+        .iter()
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()?;
+    let is_ignored = |path: &Path| is_ignored(path, &sources, &ignored);
+
+    loop {
+        let previous = read_outputs(&args.build);
+        if let Err(error) = crate::build(&args.build) {
+            eprintln!("error: {error}");
+        }
+        for (path, new) in read_outputs(&args.build) {
+            // Nothing to compare with on the first build
+            if let Some(old) = previous.get(&path)
+                && *old != new
+                && let Err(error) = print_export_diff(&path, old, &new)
+            {
+                eprintln!("error: {}: {error}", path.display());
+            }
+        }
+
+        eprintln!("Watching for changes...");
+        wait_for_change(&receiver, Duration::from_millis(args.debounce), &is_ignored)?;
+    }
+}
+
+/// Whether a change is in an ignored directory, or hidden below the sources, such as `.git`
+/// and the temporary output files. Directories above the sources may be hidden.
+fn is_ignored(path: &Path, sources: &[PathBuf], ignored: &[PathBuf]) -> bool {
+    if ignored.iter().any(|ignored| path.starts_with(ignored)) {
+        return true;
+    }
+    let relative = sources
+        .iter()
+        .filter_map(|source| path.strip_prefix(source).ok())
+        .min_by_key(|relative| relative.components().count())
+        .unwrap_or_else(|| Path::new(path.file_name().unwrap_or_default()));
+    relative
+        .components()
+        .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Reads the woven modules of the last build.
+fn read_outputs(args: &BuildArgs) -> HashMap<PathBuf, Vec<u8>> {
+    let paths = match (&args.output, &args.out_dir) {
+        (Some(output), _) => vec![output.clone()],
+        (None, Some(out_dir)) => std::fs::read_dir(out_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == "wasm" || ext == "wat")
+            })
+            .collect(),
+        (None, None) => Vec::new(),
+    };
+    paths
+        .into_iter()
+        .filter_map(|path| Some((path.clone(), std::fs::read(path).ok()?)))
+        .collect()
+}
+
+fn print_export_diff(path: &Path, old: &[u8], new: &[u8]) -> Result<(), Box<dyn Error>> {
+    let (old, new) = (text::parse(old)?, text::parse(new)?);
+    let old = ParsedModule::read(Parser::new(0), &old)?;
+    let new = ParsedModule::read(Parser::new(0), &new)?;
+
+    let changes = diff::exports(&old, &new)?;
+    if changes.is_empty() {
+        println!("Exports of {} are unchanged", path.display());
+        return Ok(());
+    }
+    println!("Exports of {} changed:", path.display());
+    for change in changes {
+        for line in change.to_string().lines() {
+            println!("  {line}");
+        }
+    }
+    Ok(())
+}
+
+/// Blocks until a file changed and no further changes arrived for `debounce`.
+fn wait_for_change(
+    receiver: &Receiver<notify::Result<Event>>,
+    debounce: Duration,
+    is_ignored: &dyn Fn(&Path) -> bool,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let event = receiver.recv()??;
+        let modified = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        );
+        if modified && event.paths.iter().any(|path| !is_ignored(path)) {
+            break;
+        }
+    }
+    loop {
+        match receiver.recv_timeout(debounce) {
+            Ok(event) => drop(event?),
+            Err(RecvTimeoutError::Timeout) => return Ok(()),
+            Err(RecvTimeoutError::Disconnected) => return Err("file watcher stopped".into()),
+        }
+    }
+}
+
+#[test]
+fn test_is_ignored() {
+    let sources = [PathBuf::from("/home/user/.local/src/example")];
+    let ignored = [PathBuf::from("/home/user/.local/src/example/target")];
+    let is_ignored = |path: &str| is_ignored(Path::new(path), &sources, &ignored);
+
+    assert!(!is_ignored("/home/user/.local/src/example/src/lib.rs"));
+    assert!(!is_ignored("/home/user/.local/src/example/Cargo.toml"));
+    assert!(is_ignored("/home/user/.local/src/example/.git/index"));
+    assert!(is_ignored(
+        "/home/user/.local/src/example/.example.wasm.tmp"
+    ));
+    assert!(is_ignored(
+        "/home/user/.local/src/example/target/release/example.wasm"
+    ));
+}
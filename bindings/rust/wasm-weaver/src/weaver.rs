@@ -1,4 +1,8 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
 
 use wasm_encoder::{
     reencode::{Reencode, utils},
@@ -6,12 +10,23 @@ use wasm_encoder::{
 };
 
 use crate::{
-    parse::{FunctionLookup, ParsedModule, TypeLookup},
+    dce::{self, DceStats, Live},
+    debug_info::{self, AddressMap, DebugInfoError, leb128_len},
+    depth::{self, DepthLimit},
+    exports::ExportFilter,
+    fuel::{self, Metering},
+    import_results::ImportResults,
+    nan::{self, Scratch, Site},
+    nodes,
+    parse::{FunctionLookup, FunctionLookupEntry, ParsedModule, RETURNS_MODULE, TypeLookup},
+    polyfill::{Linked, Polyfill},
+    returns::{self, Returns},
+    snapshot::Snapshot,
     type_allocator::HashableType,
 };
 
 #[derive(Default)]
-struct Sections {
+struct Sections<'a> {
     imports: Option<ImportSection>,
     functions: Option<FunctionSection>,
     tables: Option<TableSection>,
@@ -24,9 +39,10 @@ struct Sections {
     data_count: Option<DataCountSection>,
     data: Option<DataSection>,
     code: Option<CodeSection>,
+    custom: Vec<CustomSection<'a>>,
 }
 
-impl Sections {
+impl<'a> Sections<'a> {
     pub fn new() -> Self {
         Self::default()
     }
@@ -45,6 +61,9 @@ impl Sections {
         Self::encode_section(module, &self.data_count);
         Self::encode_section(module, &self.code);
         Self::encode_section(module, &self.data);
+        for custom in &self.custom {
+            module.section(custom);
+        }
         Ok(())
     }
 
@@ -57,16 +76,78 @@ impl Sections {
 
 #[derive(thiserror::Error, Debug)]
 pub enum WeaveError {
+    #[error("failed to parse module: {0}")]
+    Parse(#[from] wasmparser::BinaryReaderError),
+    #[error("unsupported {kind} at offset {offset:#x}")]
+    UnsupportedSection { kind: &'static str, offset: usize },
+    #[error("unknown section with id {id} at offset {offset:#x}")]
+    UnknownSection { id: u8, offset: usize },
+    #[error("the weaver can't re-encode a {0} from a parser, it needs the whole module")]
+    UnsupportedReencode(&'static str),
     #[error("type index out of bounds: {0}")]
     TypeIndexOutOfBounds(u32),
     #[error("function index out of bounds: {0}")]
     FunctionIndexOutOfBounds(u32),
+    #[error("global index out of bounds: {0}")]
+    GlobalIndexOutOfBounds(u32),
     #[error("function type is not function: {0}")]
     FunctionTypeIsNotFunction(u32),
     #[error("returns marker is not of type function: {0}")]
     ReturnsMarkerIsNotFunction(String),
-    #[error("unexpected marker function call at function {0:?}")]
-    UnexpectedMarkerFunctionCall(Option<u32>),
+    #[error("export {0} refers to an index out of bounds")]
+    ExportIndexOutOfBounds(String),
+    #[error("more than one export is named {0:?}")]
+    DuplicateExport(String),
+    #[error("import {0:?} doesn't return through a pointer to the listed results")]
+    InvalidImportResults(String),
+    #[error("invalid frooxengine.returns section: {0}")]
+    InvalidReturnsSection(String),
+    #[error("invalid frooxengine.node_info section: {0}")]
+    InvalidNodeInfo(String),
+    #[error("the {module} polyfill has no function {name:?}")]
+    UnknownPolyfill { module: &'static str, name: String },
+    #[error("import {module}.{name} doesn't have the type of its polyfill")]
+    PolyfillTypeMismatch { module: &'static str, name: String },
+    #[error("the {0} polyfill needs a memory")]
+    PolyfillWithoutMemory(&'static str),
+    #[error("marker function {0:?} is referenced without being called")]
+    MarkerFunctionReference(String),
+    #[error("call returns ({found}) from a function that already returns ({expected})")]
+    ConflictingResults { expected: String, found: String },
+    #[error("export {export:?} returns ({found}), but its marker declares ({expected})")]
+    ExportResultsMismatch {
+        export: String,
+        expected: String,
+        found: String,
+    },
+    #[error("in {location}: {error}")]
+    InFunction {
+        location: Location,
+        error: Box<WeaveError>,
+    },
+    #[error(transparent)]
+    DebugInfo(#[from] DebugInfoError),
+}
+
+/// An instruction in a function body of the input module.
+#[derive(Debug, Clone)]
+pub struct Location {
+    /// Index in the function index space, including imports.
+    pub function: u32,
+    /// The export name or, if not exported, the name from the name section.
+    pub name: Option<String>,
+    /// Byte offset in the input module.
+    pub offset: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "function {}", self.function)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        write!(f, " at offset {:#x}", self.offset)
+    }
 }
 
 impl From<WeaveError> for Error {
@@ -82,56 +163,235 @@ pub struct Weaver<'m: 'a, 'a> {
     parsed: &'m ParsedModule<'a>,
     type_map: HashMap<u32, u32>,
     fn_map: HashMap<u32, u32>,
+    global_map: HashMap<u32, u32>,
     type_indices: HashMap<HashableType, u32>,
     type_section: TypeSection,
     current_type_index: u32,
     current_fn_index: u32,
+    current_global_index: u32,
     ty_lookup: TypeLookup<'a>,
     fn_lookup: FunctionLookup<'m, 'a>,
     returns_lookup: HashMap<&'a str, (&'m [wasmparser::ValType], u32)>,
     callable_functions: Box<[bool]>,
-    /// Repalce this function index with a return instruction
-    replace_return: Option<u32>,
+    /// Results of the markers and of the functions that call them, directly or not
+    marker_results: HashMap<u32, &'m [wasmparser::ValType]>,
+    /// Results of exported functions which never reach their marker
+    export_results: HashMap<u32, &'m [wasmparser::ValType]>,
+    /// Functions with the original type for references to woven functions
+    thunks: HashMap<u32, u32>,
+    /// The function and instruction being woven, for diagnostics
+    current_location: Option<(u32, usize)>,
+    strip: bool,
+    embed_nodes: bool,
+    export_filter: ExportFilter,
+    dce: bool,
+    /// Reachable functions and globals if dead code is eliminated
+    live: Option<Live>,
+    fuel_budget: Option<u64>,
+    metering: Option<Metering>,
+    max_call_depth: Option<u32>,
+    depth_limit: Option<DepthLimit>,
+    /// Functions called by the host that reset the fuel or depth, by wrapped function
+    wrappers: Vec<(u32, u32)>,
+    /// Multi-value wrappers of exports declared in the returns section, by wrapped function
+    returns_wrappers: Vec<(u32, u32, Returns)>,
+    /// Imports with results through a pointer, the index of the real import and the results
+    import_adapters: Vec<(u32, u32, ImportResults<'a>)>,
+    canonicalize_nans: bool,
+    /// Host modules whose imports are replaced
+    polyfills: Vec<Polyfill>,
+    /// Added polyfill functions and globals with their new indices
+    linked_polyfills: Vec<Linked>,
+    /// State after running the init export, replacing the initializers
+    snapshot: Option<Snapshot>,
+    /// Records new code offsets to rewrite DWARF, which is dropped otherwise
+    address_map: Option<AddressMap>,
 }
 
 impl<'m: 'a, 'a> Weaver<'m, 'a> {
     pub fn new(parsed: &'m ParsedModule<'a>) -> Self {
-        let fn_lookup = FunctionLookup::new(&parsed);
+        let fn_lookup = FunctionLookup::new(parsed);
         let callable_functions = vec![true; fn_lookup.count()].into_boxed_slice();
         Self {
             ty_lookup: TypeLookup::new(&parsed.types),
             fn_lookup,
             returns_lookup: HashMap::new(),
             callable_functions,
+            marker_results: HashMap::new(),
+            export_results: HashMap::new(),
+            thunks: HashMap::new(),
             type_map: HashMap::new(),
             fn_map: HashMap::new(),
+            global_map: HashMap::new(),
             type_indices: HashMap::new(),
             type_section: TypeSection::new(),
             current_type_index: 0,
             current_fn_index: 0,
-            replace_return: None,
+            current_global_index: 0,
+            current_location: None,
+            strip: false,
+            embed_nodes: false,
+            export_filter: ExportFilter::keep_all(),
+            dce: false,
+            live: None,
+            fuel_budget: None,
+            metering: None,
+            max_call_depth: None,
+            depth_limit: None,
+            wrappers: Vec::new(),
+            returns_wrappers: Vec::new(),
+            import_adapters: Vec::new(),
+            canonicalize_nans: false,
+            polyfills: Vec::new(),
+            linked_polyfills: Vec::new(),
+            snapshot: None,
+            address_map: None,
             parsed,
         }
     }
 
-    pub fn encode(mut self, module: &mut Module) -> Result<()> {
+    /// Removes all custom sections.
+    pub fn strip(mut self, strip: bool) -> Self {
+        self.strip = strip;
+        self
+    }
+
+    /// Replaces the imports of these modules with pure WebAssembly functions,
+    /// see [`polyfill`] for details.
+    pub fn polyfill(mut self, polyfills: Vec<Polyfill>) -> Self {
+        self.polyfills = polyfills;
+        self
+    }
+
+    /// Adds a `frooxengine.nodes` section describing the exported functions,
+    /// see [`nodes`] for details.
+    pub fn embed_nodes(mut self, embed: bool) -> Self {
+        self.embed_nodes = embed;
+        self
+    }
+
+    /// Selects and renames the exports, all are kept by default.
+    pub fn exports(mut self, filter: ExportFilter) -> Self {
+        self.export_filter = filter;
+        self
+    }
+
+    /// Removes functions, globals, imports and types that are unreachable from the exports,
+    /// the start function and element segments.
+    pub fn eliminate_dead_code(mut self, dce: bool) -> Self {
+        self.dce = dce;
+        self
+    }
+
+    /// Meters function entries and loops, see [`fuel`] for details.
+    /// The budget of every call can be changed through an export.
+    pub fn meter_fuel(mut self, budget: Option<u64>) -> Self {
+        self.fuel_budget = budget;
+        self
+    }
+
+    /// Traps if calls nest deeper than the limit, see [`depth`] for details.
+    pub fn limit_call_depth(mut self, max: Option<u32>) -> Self {
+        self.max_call_depth = max;
+        self
+    }
+
+    /// Replaces NaNs with the canonical NaN, see [`nan`] for details.
+    pub fn canonicalize_nans(mut self, canonicalize: bool) -> Self {
+        self.canonicalize_nans = canonicalize;
+        self
+    }
+
+    /// Initializes memory and mutable globals with the snapshot and drops the start function,
+    /// see [`snapshot`](crate::snapshot) for details.
+    pub fn restore_snapshot(mut self, snapshot: Option<Snapshot>) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Keeps the `.debug_*` sections and remaps their code addresses.
+    pub fn keep_debug_info(mut self, keep: bool) -> Self {
+        self.address_map = keep.then(AddressMap::default);
+        self
+    }
+
+    /// Encodes the woven module. Returns what dead-code elimination removed, if enabled.
+    pub fn encode(mut self, module: &mut Module) -> Result<Option<DceStats>> {
         let mut sections = Sections::new();
 
         // Skip type section, encoded on demand
 
+        let exports = self.export_filter.apply(self.parsed)?;
+        let added = [
+            (self.fuel_budget.is_some(), fuel::SET_BUDGET_EXPORT),
+            (self.max_call_depth.is_some(), depth::HIGH_WATER_EXPORT),
+        ];
+        for (_, added) in added.into_iter().filter(|(is_added, _)| *is_added) {
+            if exports.iter().any(|(_, name)| name == added) {
+                return Err(WeaveError::DuplicateExport(added.to_string()).into());
+            }
+        }
+        if self.dce {
+            let kept = exports.iter().map(|(export, _)| *export);
+            self.live = Some(dce::find_live(self.parsed, kept, self.start())?);
+        }
+
         // Get and remove marker imports
+        let mut polyfilled_imports = Vec::new();
         let mut fn_import_index: u32 = 0;
-        for (index, import) in self.parsed.imports.iter().enumerate() {
-            let index32: u32 = index.try_into().unwrap();
+        let mut global_import_index: u32 = 0;
+        for import in &self.parsed.imports {
             let imports = sections.imports.get_or_insert_default();
             // Filter for namespace "__export_returns"
-            if import.module != "__export_returns" {
-                // re-encode import
-                self.parse_import(imports, *import)?;
-                if matches!(import.ty, wasmparser::TypeRef::Func(_)) {
-                    self.fn_map.insert(fn_import_index, self.current_fn_index);
-                    self.current_fn_index += 1;
-                    fn_import_index += 1;
+            if import.module != RETURNS_MODULE {
+                // re-encode import, unless it is unused
+                match import.ty {
+                    wasmparser::TypeRef::Func(ty) => {
+                        let polyfill = (self.polyfills.iter())
+                            .find(|polyfill| polyfill.module() == import.module);
+                        if let Some(&polyfill) = polyfill
+                            && self.is_live_function(fn_import_index)
+                        {
+                            polyfilled_imports.push((fn_import_index, polyfill, import.name, ty));
+                        } else if self.is_live_function(fn_import_index) {
+                            match ImportResults::parse(import)? {
+                                Some(results) => {
+                                    let marker = self.ty_lookup.try_get(ty)?.try_fn_ty()?;
+                                    let params = results.params(marker)?;
+                                    let result_types = (results.results.iter())
+                                        .map(|field| field.val_type())
+                                        .collect::<Vec<_>>();
+                                    let ty = self.new_parser_fn_ty(params, &result_types)?;
+                                    imports.import(
+                                        results.module,
+                                        results.name,
+                                        EntityType::Function(ty),
+                                    );
+                                    self.import_adapters.push((
+                                        fn_import_index,
+                                        self.current_fn_index,
+                                        results,
+                                    ));
+                                }
+                                None => {
+                                    self.parse_import(imports, *import)?;
+                                    self.fn_map.insert(fn_import_index, self.current_fn_index);
+                                }
+                            }
+                            self.current_fn_index += 1;
+                        }
+                        fn_import_index += 1;
+                    }
+                    wasmparser::TypeRef::Global(_) => {
+                        if self.is_live_global(global_import_index) {
+                            self.parse_import(imports, *import)?;
+                            self.global_map
+                                .insert(global_import_index, self.current_global_index);
+                            self.current_global_index += 1;
+                        }
+                        global_import_index += 1;
+                    }
+                    _ => self.parse_import(imports, *import)?,
                 }
                 continue;
             }
@@ -140,67 +400,272 @@ impl<'m: 'a, 'a> Weaver<'m, 'a> {
             };
             let func_ty = &self.ty_lookup.try_get(ty_idx)?.try_fn_ty()?;
             self.returns_lookup
-                .insert(import.name, (func_ty.params(), index32));
-            self.callable_functions[index] = false; // marker functions are stripped
+                .insert(import.name, (func_ty.params(), fn_import_index));
+            self.marker_results
+                .insert(fn_import_index, func_ty.params());
+            // marker functions are stripped
+            self.callable_functions[fn_import_index as usize] = false;
             fn_import_index += 1;
         }
 
         debug_assert_eq!(fn_import_index, self.fn_lookup.index_of_body(0));
 
+        // Exports and function bodies refer to globals before the global section
+        for i in 0..self.parsed.globals.len() as u32 {
+            if self.is_live_global(global_import_index + i) {
+                self.global_map
+                    .insert(global_import_index + i, self.current_global_index);
+                self.current_global_index += 1;
+            }
+        }
+
+        let thunks = self.find_marker_results()?;
+
         // Modify function types
+        let mut fn_types = HashMap::new();
         for (i, func_ty_idx) in self.parsed.functions.iter().enumerate() {
-            let functions = sections.functions.get_or_insert_default();
-            let func = self
-                .fn_lookup
-                .try_get(self.fn_lookup.index_of_body(i as u32))?;
-            let mut type_index = None;
-            'new_returns: {
-                let Some(name) = func.export_name() else {
-                    break 'new_returns;
-                };
-                let Some(&(results, _)) = self.returns_lookup.get(name) else {
-                    break 'new_returns;
-                };
-                let params = self.ty_lookup.try_get(func.ty())?.try_fn_ty()?.params();
-                type_index = Some(self.new_parser_fn_ty(params, results)?);
+            let function = self.fn_lookup.index_of_body(i as u32);
+            if !self.is_live_function(function) {
+                continue;
             }
-            functions.function(match type_index {
-                Some(index) => index,
+            let functions = sections.functions.get_or_insert_default();
+            let results = self
+                .marker_results
+                .get(&function)
+                .or(self.export_results.get(&function));
+            let ty = match results {
+                Some(results) => {
+                    let func = self.fn_lookup.try_get(function)?;
+                    let params = self.ty_lookup.try_get(func.ty())?.try_fn_ty()?.params();
+                    self.new_parser_fn_ty(params, results)?
+                }
                 None => self.type_index(*func_ty_idx)?,
+            };
+            functions.function(ty);
+            fn_types.insert(function, ty);
+            self.fn_map.insert(function, self.current_fn_index);
+            self.current_fn_index += 1;
+        }
+        for &function in &thunks {
+            let ty = self.fn_lookup.try_get(function)?.ty();
+            let functions = sections.functions.get_or_insert_default();
+            functions.function(self.type_index(ty)?);
+            self.thunks.insert(function, self.current_fn_index);
+            self.current_fn_index += 1;
+        }
+        let mut declared_returns = returns::read(self.parsed)?;
+        for name in declared_returns.keys() {
+            if !self.parsed.exports.iter().any(|export| export.name == name) {
+                let error = format!("no export named {name:?}");
+                return Err(WeaveError::InvalidReturnsSection(error).into());
+            }
+        }
+        for (export, _) in &exports {
+            if export.kind != wasmparser::ExternalKind::Func
+                || self.returns_wrapper(export.index).is_some()
+            {
+                continue;
+            }
+            let Some(returns) = declared_returns.remove(export.name) else {
+                continue;
+            };
+            let func_ty = self.fn_lookup.try_get(export.index)?.ty();
+            let func_ty = self.ty_lookup.try_get(func_ty)?.try_fn_ty()?;
+            returns.check(export.name, func_ty)?;
+            let ty = self.new_parser_fn_ty(func_ty.params(), &returns.val_types())?;
+            sections.functions.get_or_insert_default().function(ty);
+            // Fuel and depth wrappers call the multi-value wrapper
+            fn_types.insert(export.index, ty);
+            self.returns_wrappers
+                .push((export.index, self.current_fn_index, returns));
+            self.current_fn_index += 1;
+        }
+        if let Some(budget) = self.fuel_budget {
+            let functions = sections.functions.get_or_insert_default();
+            functions.function(self.new_parser_fn_ty(&[], &[])?);
+            functions.function(self.new_parser_fn_ty(&[wasmparser::ValType::I64], &[])?);
+            let exhausted = self.current_fn_index;
+            self.current_fn_index += 2;
+            self.metering = Some(Metering {
+                budget: budget.try_into().unwrap_or(i64::MAX),
+                fuel_global: self.current_global_index,
+                budget_global: self.current_global_index + 1,
+                exhausted,
+                set_budget: exhausted + 1,
+            });
+            self.current_global_index += 2;
+        }
+        if let Some(max) = self.max_call_depth {
+            let functions = sections.functions.get_or_insert_default();
+            functions.function(self.new_parser_fn_ty(&[], &[])?);
+            functions.function(self.new_parser_fn_ty(&[], &[wasmparser::ValType::I32])?);
+            let exceeded = self.current_fn_index;
+            self.current_fn_index += 2;
+            self.depth_limit = Some(DepthLimit {
+                max,
+                depth_global: self.current_global_index,
+                high_water_global: self.current_global_index + 1,
+                exceeded,
+                get_high_water: exceeded + 1,
             });
-            self.fn_map
-                .insert(fn_import_index + i as u32, self.current_fn_index);
+            self.current_global_index += 2;
+        }
+        if self.metering.is_some() || self.depth_limit.is_some() {
+            // The host only calls exported functions
+            let functions = sections.functions.get_or_insert_default();
+            for (export, _) in &exports {
+                let is_body = matches!(
+                    self.fn_lookup.get(export.index),
+                    Some(FunctionLookupEntry::Body { .. })
+                );
+                if export.kind != wasmparser::ExternalKind::Func
+                    || !is_body
+                    || self.wrapper(export.index).is_some()
+                {
+                    continue;
+                }
+                functions.function(fn_types[&export.index]);
+                self.wrappers.push((export.index, self.current_fn_index));
+                self.current_fn_index += 1;
+            }
+        }
+        // Calls and references to imports with results through a pointer use the adapters
+        for i in 0..self.import_adapters.len() {
+            let marker = self.import_adapters[i].0;
+            let ty = self.type_index(self.fn_lookup.try_get(marker)?.ty())?;
+            sections.functions.get_or_insert_default().function(ty);
+            self.fn_map.insert(marker, self.current_fn_index);
             self.current_fn_index += 1;
         }
+        for polyfill in self.polyfills.clone() {
+            let imports = (polyfilled_imports.iter())
+                .filter(|(_, p, ..)| *p == polyfill)
+                .collect::<Vec<_>>();
+            if imports.is_empty() {
+                continue;
+            }
+            let mut linked = Linked::new(polyfill, imports.iter().map(|(_, _, name, _)| *name))?;
+            if linked.uses_memory() && self.parsed.memory_type(0).is_none() {
+                return Err(WeaveError::PolyfillWithoutMemory(polyfill.module()).into());
+            }
+            for global in 0..linked.global_count() {
+                linked.global_map.insert(global, self.current_global_index);
+                self.current_global_index += 1;
+            }
+            for i in 0..linked.functions.len() {
+                let function = linked.functions[i];
+                let (ty, func_ty) = linked.func_type(function)?;
+                let ty = match linked.type_map.get(&ty) {
+                    Some(&new_ty) => new_ty,
+                    None => {
+                        let new_ty = self.new_parser_fn_ty(func_ty.params(), func_ty.results())?;
+                        linked.type_map.insert(ty, new_ty);
+                        new_ty
+                    }
+                };
+                sections.functions.get_or_insert_default().function(ty);
+                linked.function_map.insert(function, self.current_fn_index);
+                self.current_fn_index += 1;
+            }
+            // Calls and references to the imports use the polyfill
+            for &&(import, _, name, ty) in &imports {
+                let function = linked.export(name).expect("linked exports exist");
+                if self.ty_lookup.try_get(ty)?.try_fn_ty()? != linked.func_type(function)?.1 {
+                    let module = polyfill.module();
+                    let name = name.to_string();
+                    return Err(WeaveError::PolyfillTypeMismatch { module, name }.into());
+                }
+                self.fn_map.insert(import, linked.function_map[&function]);
+            }
+            self.linked_polyfills.push(linked);
+        }
 
         // Weave functions
+        let count = sections.functions.as_ref().map_or(0, FunctionSection::len);
         for (i, func_body) in self.parsed.code.iter().enumerate() {
+            let function = self.fn_lookup.index_of_body(i as u32);
+            if !self.is_live_function(function) {
+                if let Some(address_map) = self.address_map.as_mut() {
+                    let section_start = self.parsed.code_section_start as u64;
+                    let start = self.parsed.code_entry_start(i as u32) as u64 - section_start;
+                    address_map.discard(start..func_body.range().end as u64 - section_start);
+                }
+                continue;
+            }
             let code = sections.code.get_or_insert_default();
-            let func = self
-                .fn_lookup
-                .try_get(self.fn_lookup.index_of_body(i as u32))?;
-            'new_returns: {
-                let Some(name) = func.export_name() else {
-                    break 'new_returns;
-                };
-                let Some(&(_, replace_return)) = self.returns_lookup.get(name) else {
-                    break 'new_returns;
-                };
-
-                // Weave function body
-                self.replace_return = Some(replace_return);
+            self.weave_function_body(code, function, func_body, count)
+                .map_err(|error| self.in_function(error))?;
+            self.current_location = None;
+        }
+        for &function in &thunks {
+            self.encode_thunk(sections.code.get_or_insert_default(), function)?;
+        }
+        for (function, _, returns) in &self.returns_wrappers {
+            let ty = self.fn_lookup.try_get(*function)?.ty();
+            let params = self.ty_lookup.try_get(ty)?.try_fn_ty()?.params().len() as u32;
+            let code = sections.code.get_or_insert_default();
+            code.function(&returns.wrapper_body(params, self.fn_map[function]));
+        }
+        if let Some(metering) = &self.metering {
+            let code = sections.code.get_or_insert_default();
+            code.function(&metering.exhausted_body());
+            code.function(&metering.set_budget_body());
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            let code = sections.code.get_or_insert_default();
+            code.function(&depth_limit.exceeded_body());
+            code.function(&depth_limit.get_high_water_body());
+        }
+        for &(wrapped, _) in &self.wrappers {
+            let code = sections.code.get_or_insert_default();
+            code.function(&self.wrapper_body(wrapped)?);
+        }
+        for (marker, import, results) in &self.import_adapters {
+            let ty = self.fn_lookup.try_get(*marker)?.ty();
+            let params = self.ty_lookup.try_get(ty)?.try_fn_ty()?.params().len() as u32 - 1;
+            let code = sections.code.get_or_insert_default();
+            code.function(&results.adapter_body(params, *import));
+        }
+        for linked in 0..self.linked_polyfills.len() {
+            for i in 0..self.linked_polyfills[linked].functions.len() {
+                let function = self.linked_polyfills[linked].functions[i];
+                let code = sections.code.get_or_insert_default();
+                self.weave_polyfill_body(code, linked, function)?;
             }
-
-            self.parse_function_body(code, func_body.clone())?;
-            self.replace_return = None;
         }
 
-        for export in &self.parsed.exports {
-            self.parse_export(sections.exports.get_or_insert_default(), *export)?;
-
-            if export.kind != wasmparser::ExternalKind::Func {
+        for (export, name) in &exports {
+            let exports = sections.exports.get_or_insert_default();
+            if export.kind == wasmparser::ExternalKind::Func
+                && let Some(wrapper) =
+                    (self.wrapper(export.index)).or_else(|| self.returns_wrapper(export.index))
+            {
+                exports.export(name, ExportKind::Func, wrapper);
                 continue;
             }
+            if export.kind == wasmparser::ExternalKind::Func
+                && self.thunks.contains_key(&export.index)
+            {
+                // Exports use the woven function, not the thunk
+                exports.export(name, ExportKind::Func, self.fn_map[&export.index]);
+                continue;
+            }
+            let export = wasmparser::Export { name, ..**export };
+            self.parse_export(exports, export)?;
+        }
+        if let Some(metering) = &self.metering {
+            sections.exports.get_or_insert_default().export(
+                fuel::SET_BUDGET_EXPORT,
+                ExportKind::Func,
+                metering.set_budget,
+            );
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            sections.exports.get_or_insert_default().export(
+                depth::HIGH_WATER_EXPORT,
+                ExportKind::Func,
+                depth_limit.get_high_water,
+            );
         }
 
         for table in &self.parsed.tables {
@@ -208,11 +673,14 @@ impl<'m: 'a, 'a> Weaver<'m, 'a> {
             self.parse_table(sections.tables.get_or_insert_default(), table.clone())?;
         }
 
-        for memory in &self.parsed.memories {
-            sections
-                .memories
-                .get_or_insert_default()
-                .memory(self.memory_type(*memory)?);
+        for (i, memory) in self.parsed.memories.iter().enumerate() {
+            let mut ty = self.memory_type(*memory)?;
+            if i == 0
+                && let Some(pages) = self.snapshot.as_ref().and_then(Snapshot::memory_pages)
+            {
+                ty.minimum = ty.minimum.max(pages);
+            }
+            sections.memories.get_or_insert_default().memory(ty);
         }
 
         for tag in &self.parsed.tags {
@@ -222,27 +690,528 @@ impl<'m: 'a, 'a> Weaver<'m, 'a> {
                 .tag(self.tag_type(*tag)?);
         }
 
-        for global in &self.parsed.globals {
+        for (i, global) in self.parsed.globals.iter().enumerate() {
+            if !self
+                .global_map
+                .contains_key(&(global_import_index + i as u32))
+            {
+                continue;
+            }
+            let snapshot = self
+                .snapshot
+                .as_ref()
+                .and_then(|snapshot| snapshot.globals.get(&(global_import_index + i as u32)))
+                .cloned();
+            if let Some(init) = snapshot {
+                let ty = self.global_type(global.ty)?;
+                sections.globals.get_or_insert_default().global(ty, &init);
+                continue;
+            }
             // Can have initializer constexpr
             self.parse_global(sections.globals.get_or_insert_default(), global.clone())?;
         }
+        if let Some(metering) = &self.metering {
+            let globals = sections.globals.get_or_insert_default();
+            let ty = GlobalType {
+                val_type: ValType::I64,
+                mutable: true,
+                shared: false,
+            };
+            // Fuel for the start function and calls before the first reset
+            globals.global(ty, &ConstExpr::i64_const(metering.budget));
+            globals.global(ty, &ConstExpr::i64_const(metering.budget));
+        }
+        if self.depth_limit.is_some() {
+            let globals = sections.globals.get_or_insert_default();
+            let ty = GlobalType {
+                val_type: ValType::I32,
+                mutable: true,
+                shared: false,
+            };
+            globals.global(ty, &ConstExpr::i32_const(0));
+            globals.global(ty, &ConstExpr::i32_const(0));
+        }
+        for linked in &self.linked_polyfills {
+            linked.encode_globals(sections.globals.get_or_insert_default())?;
+        }
 
-        sections.start = self
-            .parsed
-            .start
-            .map(|function_index| StartSection { function_index });
+        if let Some(start) = self.start() {
+            sections.start = Some(StartSection {
+                function_index: self.function_index(start)?,
+            });
+        }
 
         for elem in &self.parsed.elements {
             self.parse_element(sections.elements.get_or_insert_default(), elem.clone())?;
         }
 
-        sections.data_count = self.parsed.start.map(|count| DataCountSection { count });
+        // Only the data section is left to restore
+        let snapshot = self.snapshot.take();
+        let segments = snapshot.as_ref().map_or(Vec::new(), Snapshot::segments);
+        sections.data_count = self.parsed.data_count.map(|count| DataCountSection {
+            count: count + segments.len() as u32,
+        });
 
         for datum in &self.parsed.data {
-            self.parse_data(sections.data.get_or_insert_default(), datum.clone())?;
+            let data = sections.data.get_or_insert_default();
+            if snapshot.is_some() && matches!(datum.kind, wasmparser::DataKind::Active { .. }) {
+                // Active segments are dropped after instantiation, which an empty one mimics
+                // without shifting the indices of passive segments
+                if self.parsed.data_count.is_some() {
+                    data.passive([]);
+                }
+                continue;
+            }
+            self.parse_data(data, datum.clone())?;
+        }
+        for (offset, bytes) in segments {
+            let data = sections.data.get_or_insert_default();
+            data.active(
+                0,
+                &ConstExpr::i32_const(offset as i32),
+                bytes.iter().copied(),
+            );
+        }
+
+        let mut debug_sections = Vec::new();
+        let custom_sections = if self.strip {
+            &[][..]
+        } else {
+            &self.parsed.custom
+        };
+        for custom in custom_sections {
+            if custom.name() == returns::SECTION || custom.name() == nodes::INFO_SECTION {
+                continue;
+            }
+            if debug_info::is_debug_section(custom) {
+                // Code offsets change, so the sections can only be kept if remapped
+                debug_sections.push(custom.clone());
+                continue;
+            }
+            let section = match custom.as_known() {
+                wasmparser::KnownCustom::Name(names) => {
+                    let names = self.custom_name_section(names)?;
+                    CustomSection {
+                        name: Cow::Borrowed("name"),
+                        data: Cow::Owned(names.as_custom().data.into_owned()),
+                    }
+                }
+                _ => self.custom_section(custom.clone())?,
+            };
+            sections.custom.push(section);
+        }
+
+        if self.embed_nodes {
+            let nodes = nodes::describe(self.parsed, &self.export_filter)?;
+            sections.custom.push(CustomSection {
+                name: Cow::Borrowed(nodes::SECTION),
+                data: Cow::Owned(serde_json::to_vec(&nodes).expect("nodes are serializable")),
+            });
+        }
+
+        if let Some(address_map) = &self.address_map {
+            let rewritten =
+                debug_info::rewrite(&debug_sections, address_map).map_err(WeaveError::from)?;
+            sections.custom.extend(rewritten);
+        }
+
+        sections.encode(module, self.type_section)?;
+        Ok(self.live.map(|live| live.stats(self.parsed)))
+    }
+
+    /// Re-encodes a function body and records the new offsets of its instructions.
+    fn weave_function_body(
+        &mut self,
+        code: &mut CodeSection,
+        function: u32,
+        body: &wasmparser::FunctionBody<'a>,
+        count: u32,
+    ) -> Result<()> {
+        self.current_location = Some((function, body.range().start));
+        let (mut func, scratch) = match self.canonicalize_nans {
+            true => self.new_function_with_scratch(function, body)?,
+            false => (self.new_function_with_parsed_locals(body)?, None),
+        };
+        let costs = match &self.metering {
+            Some(metering) => {
+                let costs = fuel::costs(body)?;
+                metering.charge(&mut func, costs[0]);
+                costs
+            }
+            None => Vec::new(),
+        };
+        if let Some(depth_limit) = &self.depth_limit {
+            depth_limit.enter(&mut func);
+            // Branches to the function label leave through the end of the block
+            let block_type = self.body_block_type(function)?;
+            func.instruction(&Instruction::Block(block_type));
+        }
+        let mut loop_costs = costs.iter().skip(1);
+        let mut offsets = Vec::new();
+        let mut reader = body.get_operators_reader()?;
+        while !reader.eof() {
+            let offset = reader.original_position();
+            self.current_location = Some((function, offset));
+            offsets.push((offset, func.byte_len()));
+            match reader.read()? {
+                wasmparser::Operator::Call { function_index }
+                    if self.marker_results.contains_key(&function_index) =>
+                {
+                    // The marker arguments are the results, any other function returns them
+                    if self.callable_functions[function_index as usize] {
+                        func.instruction(&Instruction::Call(self.fn_map[&function_index]));
+                    }
+                    self.leave(&mut func);
+                    func.instruction(&Instruction::Return);
+                }
+                wasmparser::Operator::ReturnCall { function_index }
+                    if self.marker_results.contains_key(&function_index) =>
+                {
+                    self.leave(&mut func);
+                    func.instruction(&match self.callable_functions[function_index as usize] {
+                        true => Instruction::ReturnCall(self.fn_map[&function_index]),
+                        false => Instruction::Return,
+                    });
+                }
+                op => {
+                    let instruction = self.instruction(op.clone())?;
+                    let scratch = scratch.as_ref();
+                    self.instrument(&mut func, &op, &instruction, scratch, &mut loop_costs);
+                }
+            }
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            depth_limit.leave(&mut func);
+            func.instruction(&Instruction::End);
+        }
+        offsets.push((body.range().end, func.byte_len()));
+
+        let Some(address_map) = self.address_map.as_mut() else {
+            code.function(&func);
+            return Ok(());
+        };
+
+        let section_start = self.parsed.code_section_start as u64;
+        let entry = function - self.fn_lookup.index_of_body(0);
+        let old_start = self.parsed.code_entry_start(entry) as u64 - section_start;
+        // The function count precedes the bodies in the section
+        let new_start = leb128_len(count as u64) + code.byte_len() as u64;
+        let new_body = new_start + leb128_len(func.byte_len() as u64);
+
+        address_map.insert(old_start, new_start);
+        for (old, new) in offsets {
+            address_map.insert(old as u64 - section_start, new_body + new as u64);
+        }
+
+        code.function(&func);
+        Ok(())
+    }
+
+    /// Adds a polyfill function with the same fuel, call depth and NaN instrumentation as
+    /// the functions of the module.
+    fn weave_polyfill_body(
+        &mut self,
+        code: &mut CodeSection,
+        linked: usize,
+        function: u32,
+    ) -> Result<()> {
+        let (_, func_ty) = self.linked_polyfills[linked].func_type(function)?;
+        let mut next_local = func_ty.params().len() as u32;
+        let results = func_ty.results().to_vec();
+        let block_type = match self.depth_limit {
+            Some(_) => Some(self.results_block_type(&results)?),
+            None => None,
+        };
+        let linked = &self.linked_polyfills[linked];
+        let body = linked.body(function);
+        let mut relocate = linked.relocate();
+        let mut locals = Vec::new();
+        for pair in body.get_locals_reader()? {
+            let (count, ty) = pair?;
+            locals.push((count, relocate.val_type(ty)?));
+            next_local += count;
+        }
+        let scratch = match self.canonicalize_nans {
+            true => {
+                let (scratch, scratch_locals) = Scratch::for_body(body, next_local)?;
+                locals.extend(scratch_locals.into_iter().map(|ty| (1, ty)));
+                Some(scratch)
+            }
+            false => None,
+        };
+        let mut func = Function::new(locals);
+        let costs = match &self.metering {
+            Some(metering) => {
+                let costs = fuel::costs(body)?;
+                metering.charge(&mut func, costs[0]);
+                costs
+            }
+            None => Vec::new(),
+        };
+        if let (Some(depth_limit), Some(block_type)) = (&self.depth_limit, block_type) {
+            depth_limit.enter(&mut func);
+            func.instruction(&Instruction::Block(block_type));
         }
+        let mut loop_costs = costs.iter().skip(1);
+        for op in body.get_operators_reader()? {
+            let op = op?;
+            let instruction = relocate.instruction(op.clone())?;
+            self.instrument(
+                &mut func,
+                &op,
+                &instruction,
+                scratch.as_ref(),
+                &mut loop_costs,
+            );
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            depth_limit.leave(&mut func);
+            func.instruction(&Instruction::End);
+        }
+        code.function(&func);
+        Ok(())
+    }
 
-        sections.encode(module, self.type_section)
+    /// Encodes an instruction, charging fuel after loop headers, leaving before returns and
+    /// canonicalizing NaNs.
+    fn instrument<'c>(
+        &self,
+        func: &mut Function,
+        op: &wasmparser::Operator,
+        instruction: &Instruction,
+        scratch: Option<&Scratch>,
+        loop_costs: &mut impl Iterator<Item = &'c u64>,
+    ) {
+        let site = scratch.and_then(|scratch| Some((scratch, nan::site(op)?)));
+        if let Some((scratch, Site::Operand(float))) = site {
+            scratch.canonicalize(func, float);
+        }
+        if matches!(
+            op,
+            wasmparser::Operator::Return
+                | wasmparser::Operator::ReturnCall { .. }
+                | wasmparser::Operator::ReturnCallIndirect { .. }
+                | wasmparser::Operator::ReturnCallRef { .. }
+        ) {
+            self.leave(func);
+        }
+        func.instruction(instruction);
+        if let Some((scratch, Site::Result(float))) = site {
+            scratch.canonicalize(func, float);
+        }
+        if let (wasmparser::Operator::Loop { .. }, Some(metering)) = (op, &self.metering)
+            && let Some(&cost) = loop_costs.next()
+        {
+            metering.charge(func, cost);
+        }
+    }
+
+    /// Finds the functions that return through a marker, directly or by calling such a
+    /// function, e.g. outlined or merged `#[export_function]` wrappers.
+    /// Returns the woven functions that need a thunk with their original type.
+    fn find_marker_results(&mut self) -> Result<Vec<u32>> {
+        let mut referenced = BTreeSet::new();
+        let calls = read_calls(self.parsed, &mut referenced)?;
+        let first_body = self.fn_lookup.index_of_body(0);
+        if let Err(conflict) = propagate_results(&calls, first_body, &mut self.marker_results) {
+            self.current_location = Some((conflict.function, conflict.offset));
+            return Err(self.in_function(conflict.into_error().into()));
+        }
+
+        for export in &self.parsed.exports {
+            if export.kind != wasmparser::ExternalKind::Func {
+                continue;
+            }
+            let Some(&(expected, _)) = self.returns_lookup.get(export.name) else {
+                continue;
+            };
+            // The body may never reach the marker, e.g. if it always traps
+            let found = *self
+                .marker_results
+                .get(&export.index)
+                .unwrap_or_else(|| self.export_results.entry(export.index).or_insert(expected));
+            if found != expected {
+                return Err(WeaveError::ExportResultsMismatch {
+                    export: export.name.to_string(),
+                    expected: format_types(expected),
+                    found: format_types(found),
+                }
+                .into());
+            }
+        }
+
+        // Tables, globals and the start function expect the original type
+        for element in &self.parsed.elements {
+            match &element.items {
+                wasmparser::ElementItems::Functions(functions) => {
+                    for function in functions.clone() {
+                        referenced.insert(function?);
+                    }
+                }
+                wasmparser::ElementItems::Expressions(_, exprs) => {
+                    for expr in exprs.clone() {
+                        const_expr_refs(&expr?, &mut referenced)?;
+                    }
+                }
+            }
+        }
+        for global in &self.parsed.globals {
+            const_expr_refs(&global.init_expr, &mut referenced)?;
+        }
+        for table in &self.parsed.tables {
+            if let wasmparser::TableInit::Expr(expr) = &table.init {
+                const_expr_refs(expr, &mut referenced)?;
+            }
+        }
+        referenced.extend(self.parsed.start);
+
+        Ok(referenced
+            .into_iter()
+            .filter(|function| {
+                self.callable_functions.get(*function as usize) == Some(&true)
+                    && self.marker_results.contains_key(function)
+                    // References from removed functions don't need a thunk
+                    && self.is_live_function(*function)
+            })
+            .collect())
+    }
+
+    /// Adds the scratch locals for NaN canonicalization after the locals of the body.
+    fn new_function_with_scratch(
+        &mut self,
+        function: u32,
+        body: &wasmparser::FunctionBody<'a>,
+    ) -> Result<(Function, Option<Scratch>)> {
+        let ty = self.fn_lookup.try_get(function)?.ty();
+        let mut next_local = self.ty_lookup.try_get(ty)?.try_fn_ty()?.params().len() as u32;
+        let mut locals = Vec::new();
+        for pair in body.get_locals_reader()? {
+            let (count, ty) = pair?;
+            locals.push((count, self.val_type(ty)?));
+            next_local += count;
+        }
+        let (scratch, scratch_locals) = Scratch::for_body(body, next_local)?;
+        locals.extend(scratch_locals.into_iter().map(|ty| (1, ty)));
+        Ok((Function::new(locals), Some(scratch)))
+    }
+
+    fn is_live_function(&self, function: u32) -> bool {
+        self.live
+            .as_ref()
+            .is_none_or(|live| live.functions[function as usize])
+    }
+
+    fn is_live_global(&self, global: u32) -> bool {
+        self.live
+            .as_ref()
+            .is_none_or(|live| live.globals[global as usize])
+    }
+
+    /// The start function, unless it already ran for the snapshot.
+    fn start(&self) -> Option<u32> {
+        self.parsed.start.filter(|_| self.snapshot.is_none())
+    }
+
+    fn wrapper(&self, function: u32) -> Option<u32> {
+        self.wrappers
+            .iter()
+            .find(|(wrapped, _)| *wrapped == function)
+            .map(|(_, wrapper)| *wrapper)
+    }
+
+    /// Resets the fuel and call depth and calls the wrapped function.
+    fn wrapper_body(&self, wrapped: u32) -> Result<Function> {
+        let ty = self.fn_lookup.try_get(wrapped)?.ty();
+        let params = self.ty_lookup.try_get(ty)?.try_fn_ty()?.params().len() as u32;
+        let mut func = Function::new([]);
+        if let Some(metering) = &self.metering {
+            metering.reset(&mut func);
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            depth_limit.reset(&mut func);
+        }
+        for local in 0..params {
+            func.instruction(&Instruction::LocalGet(local));
+        }
+        let callee = (self.returns_wrapper(wrapped)).unwrap_or(self.fn_map[&wrapped]);
+        func.instruction(&Instruction::Call(callee))
+            .instruction(&Instruction::End);
+        Ok(func)
+    }
+
+    fn returns_wrapper(&self, function: u32) -> Option<u32> {
+        self.returns_wrappers
+            .iter()
+            .find(|(wrapped, ..)| *wrapped == function)
+            .map(|(_, wrapper, _)| *wrapper)
+    }
+
+    fn leave(&self, func: &mut Function) {
+        if let Some(depth_limit) = &self.depth_limit {
+            depth_limit.leave(func);
+        }
+    }
+
+    /// The type of a block with the woven results of a function.
+    fn body_block_type(&mut self, function: u32) -> Result<BlockType> {
+        let results = match self
+            .marker_results
+            .get(&function)
+            .or(self.export_results.get(&function))
+        {
+            Some(results) => *results,
+            None => {
+                let ty = self.fn_lookup.try_get(function)?.ty();
+                self.ty_lookup.try_get(ty)?.try_fn_ty()?.results()
+            }
+        };
+        self.results_block_type(results)
+    }
+
+    fn results_block_type(&mut self, results: &[wasmparser::ValType]) -> Result<BlockType> {
+        Ok(match results {
+            [] => BlockType::Empty,
+            [result] => BlockType::Result(self.val_type(*result)?),
+            _ => BlockType::FunctionType(self.new_parser_fn_ty(&[], results)?),
+        })
+    }
+
+    /// Calls a woven function with its original type. Like the marker, it never returns.
+    fn encode_thunk(&mut self, code: &mut CodeSection, function: u32) -> Result<()> {
+        let ty = self.fn_lookup.try_get(function)?.ty();
+        let params = self.ty_lookup.try_get(ty)?.try_fn_ty()?.params().len() as u32;
+        let mut func = Function::new([]);
+        for local in 0..params {
+            func.instruction(&Instruction::LocalGet(local));
+        }
+        func.instruction(&Instruction::Call(self.fn_map[&function]));
+        func.instruction(&Instruction::Unreachable);
+        func.instruction(&Instruction::End);
+        code.function(&func);
+        Ok(())
+    }
+
+    /// Attaches the location of the current instruction to an error.
+    fn in_function(&self, error: Error) -> Error {
+        match (error, self.current_location) {
+            (Error::UserError(error), Some((function, offset))) => {
+                let name = self
+                    .fn_lookup
+                    .get(function)
+                    .and_then(|func| func.export_name())
+                    .or_else(|| self.parsed.function_names().get(&function).copied());
+                WeaveError::InFunction {
+                    location: Location {
+                        function,
+                        name: name.map(str::to_string),
+                        offset,
+                    },
+                    error: Box::new(error),
+                }
+                .into()
+            }
+            (error, _) => error,
+        }
     }
 
     fn new_parser_fn_ty(
@@ -285,7 +1254,48 @@ impl<'m: 'a, 'a> Weaver<'m, 'a> {
         self.type_indices
             .insert(sub_type.into_owned().into(), ty_idx);
 
-        return ty_idx;
+        ty_idx
+    }
+
+    /// Names the functions added by weaving after the function they wrap, or the import or
+    /// polyfill they call.
+    fn added_function_names(&self, map: wasmparser::NameMap<'_>) -> Result<Vec<(u32, String)>> {
+        let mut original = HashMap::new();
+        for naming in map {
+            let naming = naming?;
+            original.insert(naming.index, naming.name);
+        }
+        let name = |function: u32| {
+            (original.get(&function).copied())
+                .or_else(|| self.fn_lookup.get(function)?.export_name())
+        };
+
+        let mut added = Vec::new();
+        for (&function, &thunk) in &self.thunks {
+            added.extend(name(function).map(|name| (thunk, format!("{name}.thunk"))));
+        }
+        for &(function, wrapper, _) in &self.returns_wrappers {
+            added.extend(name(function).map(|name| (wrapper, format!("{name}.returns"))));
+        }
+        if let Some(metering) = &self.metering {
+            added.push((metering.exhausted, fuel::EXHAUSTED_NAME.into()));
+            added.push((metering.set_budget, fuel::SET_BUDGET_EXPORT.into()));
+        }
+        if let Some(depth_limit) = &self.depth_limit {
+            added.push((depth_limit.exceeded, depth::EXCEEDED_NAME.into()));
+            added.push((depth_limit.get_high_water, depth::HIGH_WATER_EXPORT.into()));
+        }
+        for &(function, wrapper) in &self.wrappers {
+            added.extend(name(function).map(|name| (wrapper, format!("{name}.wrapper"))));
+        }
+        // The adapters take the names of the marker imports
+        for (_, import, results) in &self.import_adapters {
+            added.push((*import, format!("{}.{}", results.module, results.name)));
+        }
+        for linked in &self.linked_polyfills {
+            added.extend(linked.function_names());
+        }
+        Ok(added)
     }
 }
 
@@ -329,35 +1339,68 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         }
     }
 
-    fn instruction<'o>(
-        &mut self,
-        arg: wasmparser::Operator<'o>,
-    ) -> Result<wasm_encoder::Instruction<'o>> {
-        if let Some(func) = self.replace_return {
-            match arg {
-                wasmparser::Operator::Call { function_index } if function_index == func => {
-                    // Replace call with return
-                    return Ok(Instruction::Return);
-                }
-                _ => (),
-            }
-        }
-        utils::instruction(self, arg)
-    }
-
-    /// Prevents calls to marker functions
+    /// Prevents references to marker functions and refers to thunks instead of woven functions
     fn function_index(&mut self, func: u32) -> Result<u32> {
+        if let Some(&thunk) = self.thunks.get(&func) {
+            return Ok(thunk);
+        }
         let index: usize = func.try_into().unwrap();
         match self.callable_functions.get(index) {
             Some(true) => match self.fn_map.get(&func) {
                 Some(idx) => Ok(*idx),
                 None => Err(WeaveError::FunctionIndexOutOfBounds(func).into()),
             },
-            Some(false) => Err(WeaveError::UnexpectedMarkerFunctionCall(None).into()),
+            Some(false) => {
+                let marker = self
+                    .returns_lookup
+                    .iter()
+                    .find(|(_, (_, index))| *index == func)
+                    .map(|(name, _)| name.to_string())
+                    .unwrap_or_default();
+                Err(WeaveError::MarkerFunctionReference(marker).into())
+            }
             None => Err(WeaveError::FunctionIndexOutOfBounds(func).into()),
         }
     }
 
+    fn global_index(&mut self, global: u32) -> Result<u32> {
+        match self.global_map.get(&global) {
+            Some(idx) => Ok(*idx),
+            None => Err(WeaveError::GlobalIndexOutOfBounds(global).into()),
+        }
+    }
+
+    /// Remaps names through the index maps, dropping names of stripped functions and unused types.
+    fn parse_custom_name_subsection(
+        &mut self,
+        names: &mut NameSection,
+        section: wasmparser::Name<'_>,
+    ) -> Result<()> {
+        match section {
+            wasmparser::Name::Function(map) => {
+                let added = self.added_function_names(map.clone())?;
+                names.functions(&remap_name_map(map, &self.fn_map, added)?);
+            }
+            wasmparser::Name::Local(map) => {
+                names.locals(&remap_indirect_name_map(map, &self.fn_map)?);
+            }
+            wasmparser::Name::Label(map) => {
+                names.labels(&remap_indirect_name_map(map, &self.fn_map)?);
+            }
+            wasmparser::Name::Global(map) => {
+                names.globals(&remap_name_map(map, &self.global_map, Vec::new())?);
+            }
+            wasmparser::Name::Type(map) => {
+                names.types(&remap_name_map(map, &self.type_map, Vec::new())?);
+            }
+            wasmparser::Name::Field(map) => {
+                names.fields(&remap_indirect_name_map(map, &self.type_map)?);
+            }
+            section => utils::parse_custom_name_subsection(self, names, section)?,
+        }
+        Ok(())
+    }
+
     /// Weaver needs to know the whole module ahead of time,
     /// a parser is not sufficient to reencode.
     fn parse_core_module(
@@ -366,7 +1409,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _parser: wasmparser::Parser,
         _data: &[u8],
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("module").into())
     }
 
     fn parse_type_section(
@@ -374,7 +1417,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _types: &mut wasm_encoder::TypeSection,
         _section: wasmparser::TypeSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("type section").into())
     }
 
     fn parse_import_section(
@@ -382,7 +1425,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _imports: &mut wasm_encoder::ImportSection,
         _section: wasmparser::ImportSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("import section").into())
     }
 
     fn parse_function_section(
@@ -390,7 +1433,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _functions: &mut wasm_encoder::FunctionSection,
         _section: wasmparser::FunctionSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("function section").into())
     }
 
     fn parse_table_section(
@@ -398,7 +1441,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _tables: &mut wasm_encoder::TableSection,
         _section: wasmparser::TableSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("table section").into())
     }
 
     fn parse_memory_section(
@@ -406,7 +1449,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _memories: &mut wasm_encoder::MemorySection,
         _section: wasmparser::MemorySectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("memory section").into())
     }
 
     fn parse_tag_section(
@@ -414,7 +1457,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _tags: &mut wasm_encoder::TagSection,
         _section: wasmparser::TagSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("tag section").into())
     }
 
     fn parse_global_section(
@@ -422,7 +1465,7 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _globals: &mut wasm_encoder::GlobalSection,
         _section: wasmparser::GlobalSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("global section").into())
     }
 
     fn parse_export_section(
@@ -430,6 +1473,347 @@ impl<'m: 'a, 'a> Reencode for Weaver<'m, 'a> {
         _exports: &mut wasm_encoder::ExportSection,
         _section: wasmparser::ExportSectionReader<'_>,
     ) -> Result<()> {
-        panic!("not supported")
+        Err(WeaveError::UnsupportedReencode("export section").into())
+    }
+}
+
+/// The calls in every function body with their offsets. Functions referenced by `ref.func`
+/// are added to `referenced`.
+pub(crate) fn read_calls(
+    parsed: &ParsedModule,
+    referenced: &mut BTreeSet<u32>,
+) -> Result<Vec<Vec<(u32, usize)>>, wasmparser::BinaryReaderError> {
+    let mut calls = Vec::with_capacity(parsed.code.len());
+    for body in &parsed.code {
+        let mut body_calls = Vec::new();
+        let mut reader = body.get_operators_reader()?;
+        while !reader.eof() {
+            let offset = reader.original_position();
+            match reader.read()? {
+                wasmparser::Operator::Call { function_index }
+                | wasmparser::Operator::ReturnCall { function_index } => {
+                    body_calls.push((function_index, offset));
+                }
+                wasmparser::Operator::RefFunc { function_index } => {
+                    referenced.insert(function_index);
+                }
+                _ => (),
+            }
+        }
+        calls.push(body_calls);
+    }
+    Ok(calls)
+}
+
+/// A call returning other results than its caller.
+pub(crate) struct Conflict<'r> {
+    pub function: u32,
+    pub offset: usize,
+    pub expected: &'r [wasmparser::ValType],
+    pub found: &'r [wasmparser::ValType],
+}
+
+impl Conflict<'_> {
+    pub fn into_error(self) -> WeaveError {
+        WeaveError::ConflictingResults {
+            expected: format_types(self.expected),
+            found: format_types(self.found),
+        }
+    }
+}
+
+/// Adds the results of every function calling a function with results, directly or not, e.g.
+/// outlined or merged `#[export_function]` wrappers calling a marker.
+pub(crate) fn propagate_results<'r>(
+    calls: &[Vec<(u32, usize)>],
+    first_body: u32,
+    results: &mut HashMap<u32, &'r [wasmparser::ValType]>,
+) -> Result<(), Conflict<'r>> {
+    // Results flow from callees to callers
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, body_calls) in calls.iter().enumerate() {
+            let function = first_body + i as u32;
+            for &(callee, offset) in body_calls {
+                let Some(&found) = results.get(&callee) else {
+                    continue;
+                };
+                match results.get(&function) {
+                    None => {
+                        results.insert(function, found);
+                        changed = true;
+                    }
+                    Some(&expected) if expected != found => {
+                        return Err(Conflict {
+                            function,
+                            offset,
+                            expected,
+                            found,
+                        });
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn const_expr_refs(expr: &wasmparser::ConstExpr<'_>, refs: &mut BTreeSet<u32>) -> Result<()> {
+    for op in expr.get_operators_reader() {
+        if let wasmparser::Operator::RefFunc { function_index } = op? {
+            refs.insert(function_index);
+        }
+    }
+    Ok(())
+}
+
+fn format_types(types: &[wasmparser::ValType]) -> String {
+    let types = types.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+    types.join(" ")
+}
+
+/// Re-encodes a name map, keeping only the names of items that are still present,
+/// and names the added items.
+/// Names of the module take precedence over the added names, e.g. for replaced imports.
+fn remap_name_map(
+    map: wasmparser::NameMap<'_>,
+    index_map: &HashMap<u32, u32>,
+    added: Vec<(u32, String)>,
+) -> Result<NameMap> {
+    // Type indices are assigned on demand and may be out of order
+    let mut names = added.into_iter().collect::<BTreeMap<_, _>>();
+    for naming in map {
+        let naming = naming?;
+        if let Some(&index) = index_map.get(&naming.index) {
+            names.insert(index, naming.name.to_string());
+        }
+    }
+
+    let mut ret = NameMap::new();
+    for (index, name) in &names {
+        ret.append(*index, name);
+    }
+    Ok(ret)
+}
+
+fn remap_indirect_name_map(
+    map: wasmparser::IndirectNameMap<'_>,
+    index_map: &HashMap<u32, u32>,
+) -> Result<IndirectNameMap> {
+    let mut names = Vec::new();
+    for naming in map {
+        let naming = naming?;
+        if let Some(&index) = index_map.get(&naming.index) {
+            names.push((index, utils::name_map(naming.names, Ok)?));
+        }
+    }
+    names.sort_by_key(|(index, _)| *index);
+
+    let mut ret = IndirectNameMap::new();
+    for (index, names) in &names {
+        ret.append(*index, names);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+fn weave_wat(wat: &str) -> Vec<u8> {
+    crate::weave(&wat::parse_str(wat).unwrap()).unwrap()
+}
+
+#[test]
+fn test_name_section_remapped() {
+    let wasm = weave_wat(
+        r#"(module $example
+            (import "math" "sin_f32" (func $sin (param f32) (result f32)))
+            (import "__export_returns" "foo" (func $ret (param i32 i32)))
+            (import "math" "cos_f32" (func $cos (param f32) (result f32)))
+            (func $foo (export "foo") (param $x i32)
+                local.get $x
+                local.get $x
+                call $ret
+                unreachable)
+            (@custom "producers" "\00"))"#,
+    );
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+    let mut functions = Vec::new();
+    let mut locals = Vec::new();
+    let mut custom_names = Vec::new();
+    for custom in &parsed.custom {
+        custom_names.push(custom.name());
+        let wasmparser::KnownCustom::Name(names) = custom.as_known() else {
+            continue;
+        };
+        for name in names {
+            match name.unwrap() {
+                wasmparser::Name::Function(map) => {
+                    for naming in map {
+                        let naming = naming.unwrap();
+                        functions.push((naming.index, naming.name));
+                    }
+                }
+                wasmparser::Name::Local(map) => {
+                    for naming in map {
+                        locals.push(naming.unwrap().index);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    assert_eq!(custom_names, ["name", "producers"]);
+    assert_eq!(functions, [(0, "sin"), (1, "cos"), (2, "foo")]);
+    assert_eq!(locals, [2]);
+}
+
+#[test]
+fn test_conflicting_results_location() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "__export_returns" "foo" (func $foo (param i32)))
+            (import "__export_returns" "bar" (func $bar (param i64)))
+            (func (export "foo") (param i32)
+                local.get 0
+                if
+                    i32.const 1
+                    call $foo
+                end
+                i64.const 2
+                call $bar
+                unreachable))"#,
+    )
+    .unwrap();
+
+    let Err(crate::Error::Weave(WeaveError::InFunction { location, error })) = crate::weave(&wasm)
+    else {
+        panic!("expected an error in a function body");
+    };
+    assert_eq!(location.function, 2);
+    assert_eq!(location.name.as_deref(), Some("foo"));
+    assert!(matches!(*error, WeaveError::ConflictingResults { .. }));
+    assert_eq!(&wasm[location.offset..][..2], [0x10, 0x01]); // call $bar
+}
+
+#[test]
+fn test_outlined_and_aliased() {
+    let wasm = weave_wat(
+        r#"(module
+            (import "__export_returns" "foo" (func $ret (param i32 f32)))
+            (func $foo (export "foo") (export "bar") (param i32)
+                local.get 0
+                call $outlined
+                unreachable)
+            (func $outlined (param i32)
+                local.get 0
+                f32.const 1
+                call $ret
+                unreachable)
+            (table funcref (elem $foo)))"#,
+    );
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &wasm).unwrap();
+
+    let exports = crate::inspect::exports(&parsed, &ExportFilter::default()).unwrap();
+    let signatures = exports
+        .iter()
+        .map(|export| export.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        signatures,
+        [
+            "func   foo: (int -> int float) ; (param i32)(result i32 f32)",
+            "func   bar: (int -> int float) ; (param i32)(result i32 f32)",
+        ]
+    );
+
+    // The table keeps the original type through a thunk
+    let wasmparser::ElementItems::Functions(functions) = &parsed.elements[0].items else {
+        panic!("expected function indices");
+    };
+    let thunk = functions.clone().into_iter().next().unwrap().unwrap();
+    assert_eq!(thunk, 2);
+    let thunk_ty = parsed.functions[thunk as usize];
+    let types = TypeLookup::new(&parsed.types);
+    let thunk_ty = types.try_get(thunk_ty).unwrap().try_fn_ty().unwrap();
+    assert_eq!(thunk_ty.results(), []);
+}
+
+#[test]
+fn test_dead_code_eliminated() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f32" (func $sin (param f32) (result f32)))
+            (import "__export_returns" "foo" (func $ret (param i32 i32)))
+            (import "math" "cos_f64" (func $cos (param f64) (result f64)))
+            (import "env" "unused" (global $imported i64))
+            (global $unused (mut i64) (i64.const 0))
+            (global $used (mut i32) (i32.const 0))
+            (func $foo (export "foo") (param i32)
+                local.get 0
+                global.get $used
+                call $ret
+                unreachable)
+            (func $dead (param f64) (result f64)
+                global.get $unused
+                drop
+                local.get 0
+                call $cos)
+            (func $start
+                i32.const 1
+                global.set $used)
+            (start $start)
+            (table funcref (elem $foo)))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        dce: true,
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &woven).unwrap();
+
+    // $foo, $start and the thunk of $foo for the table
+    assert!(parsed.imports.is_empty());
+    assert_eq!(parsed.globals.len(), 1);
+    assert_eq!(parsed.code.len(), 3);
+    assert_eq!(parsed.types.len(), 3);
+    let names = parsed.function_names();
+    assert_eq!(names.len(), 3);
+    assert_eq!(names.get(&1).copied(), Some("start"));
+    assert_eq!(names.get(&2).copied(), Some("foo.thunk"));
+    assert_eq!(parsed.start, Some(1));
+}
+
+#[test]
+fn test_added_functions_named() {
+    let wasm = wat::parse_str(
+        r#"(module
+            (import "math" "sin_f64" (func (param f64) (result f64)))
+            (func $foo (export "foo") (param f64) (result f64)
+                local.get 0
+                call 0))"#,
+    )
+    .unwrap();
+    let options = crate::Options {
+        fuel: Some(1000),
+        polyfills: vec![Polyfill::Math],
+        ..Default::default()
+    };
+    let woven = crate::weave_with(&wasm, &options).unwrap();
+    let parsed = ParsedModule::read(wasmparser::Parser::new(0), &woven).unwrap();
+
+    let names = parsed.function_names();
+    let mut names = names.values().copied().collect::<Vec<_>>();
+    names.sort();
+    for name in [
+        "foo",
+        "foo.wrapper",
+        fuel::EXHAUSTED_NAME,
+        "math.sin_f64",
+        "math.sin",
+        "math.rem_pio2",
+    ] {
+        assert!(names.contains(&name), "{name} not in {names:?}");
     }
 }